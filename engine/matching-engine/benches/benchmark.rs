@@ -1,26 +1,94 @@
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use uuid::Uuid;
-use matching_engine::{engine::market::MarketRegistry, models::*};
+use matching_engine::{engine::{config::MarketConfig, market::MarketRegistry}, models::*};
 
-fn engine_benchmark(c: &mut Criterion) {
+fn order(side: Side, price: u64, quantity: u64, timestamp: u64) -> Order {
+    Order {
+        id: Uuid::new_v4(),
+        market: "ETH-USD".into(),
+        wallet: "bot".into(),
+        side,
+        price: Price(price),
+        quantity,
+        timestamp,
+        order_type: OrderType::Limit,
+        time_in_force: TimeInForce::Gtc,
+        display_quantity: None,
+        hidden_quantity: 0,
+        self_trade_prevention: SelfTradePrevention::None,
+        client_order_id: None,
+    }
+}
+
+fn registry() -> MarketRegistry {
     let mut registry = MarketRegistry::new();
+    registry.register(MarketConfig::from_symbol("ETH-USD")).unwrap();
+    // Orders lock funds while open, so the bot needs enough for any run
+    for asset in ["ETH", "USD"] {
+        registry.deposit("bot", asset, u64::MAX as u128, 0).unwrap();
+    }
+    registry
+}
+
+/// Registry with `depth` bids resting at one price, oldest first
+fn deep_level(depth: usize) -> (MarketRegistry, Vec<Uuid>) {
+    let mut registry = registry();
+    let ids = (0..depth)
+        .map(|_| {
+            let bid = order(Side::Buy, 2000, 1, 0);
+            let id = bid.id;
+            registry.submit(bid).unwrap();
+            id
+        })
+        .collect();
+    (registry, ids)
+}
+
+fn engine_benchmark(c: &mut Criterion) {
+    let mut registry = registry();
 
     c.bench_function("submit 1000 orders", |b| {
         b.iter(|| {
             for i in 0..1000 {
-                registry.submit(Order {
-                    id: Uuid::new_v4(),
-                    market: "ETH-USD".into(),
-                    wallet: "bot".into(),
-                    side: if i % 2 == 0 { Side::Buy } else { Side::Sell },
-                    price: Price(2000),
-                    quantity: 1,
-                    timestamp: i,
-                });
+                let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
+                registry.submit(order(side, 2000, 1, i)).unwrap();
             }
         })
     });
 }
 
-criterion_group!(benches, engine_benchmark);
+/// Cancel/replace churn from the middle of a level should cost the same
+/// whatever the level's depth
+fn cancel_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cancel and replace mid-level");
+    for depth in [100, 10_000, 100_000] {
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &depth, |b, &depth| {
+            let (mut registry, mut ids) = deep_level(depth);
+            let mut next = 0;
+            b.iter(|| {
+                let slot = depth / 2 + next % (depth / 2);
+                next += 1;
+
+                registry.cancel("ETH-USD", ids[slot], "bot").unwrap();
+                let replacement = order(Side::Buy, 2000, 1, 0);
+                ids[slot] = replacement.id;
+                registry.submit(replacement).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+/// A sweep that fills a whole level, exercising fills and level removal
+fn sweep_benchmark(c: &mut Criterion) {
+    c.bench_function("sweep 1000-order level", |b| {
+        b.iter_batched(
+            || deep_level(1000).0,
+            |mut registry| registry.submit(order(Side::Sell, 2000, 1000, 0)).unwrap(),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, engine_benchmark, cancel_benchmark, sweep_benchmark);
 criterion_main!(benches);
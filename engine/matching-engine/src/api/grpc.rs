@@ -1,8 +1,27 @@
-use tonic::{Request, Response, Status};
+// tonic::Status is large, but it is what every handler returns anyway
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use prost::Message;
+use tonic::{Code, Request, Response, Status, Streaming};
 use uuid::Uuid;
 
+use crate::api::session::{Sessions, SessionConfig};
+use crate::api::ws::WSServer;
+use crate::engine::config::{MarketConfig, MatchingMode, BatchArrival};
+use crate::engine::error::EngineError;
+use crate::engine::fees::{FeeRates, FeeSchedule, FeeTier};
+use crate::engine::lifecycle::OrderReport;
 use crate::engine::market::MarketRegistry;
-use crate::models::{order::Order, side::Side, price::Price};
+use crate::engine::mmp::MmpLimits;
+use crate::engine::outcome::{SubmitOutcome, RejectReason, CancelResult};
+use crate::engine::risk::{RiskLimits, PriceCollar, CollarReference, MaxQuantity, MaxNotional, MaxOpenOrders};
+use crate::engine::state::TradingState;
+use crate::models::{order::Order, amendment::Amendment, order_type::{OrderType, Protection}, time_in_force::TimeInForce, self_trade::SelfTradePrevention, side::Side, price::Price};
 
 pub mod engine_proto {
     tonic::include_proto!("engine");
@@ -11,9 +30,537 @@ pub mod engine_proto {
 use engine_proto::matching_engine_server::MatchingEngine;
 use engine_proto::*;
 
-#[derive(Default)]
+/// gRPC front end to the registry; clones share the registry, the event
+/// publisher and the session table, so session tasks can outlive a request
+#[derive(Clone, Default)]
 pub struct GrpcEngine {
-    pub registry: tokio::sync::Mutex<MarketRegistry>,
+    pub registry: Arc<Mutex<MarketRegistry>>,
+    pub ws: Arc<WSServer>,
+    pub sessions: Arc<Sessions>,
+    pub session_config: SessionConfig,
+    /// Authenticated identities allowed to credit custody and pull orders
+    pub operators: Arc<HashSet<String>>,
+}
+
+/// Flagged orders are checked against the book whenever a wallet's count
+/// reaches a power of two from this size, so fills don't pile up
+const SESSION_PRUNE_THRESHOLD: usize = 1024;
+
+impl GrpcEngine {
+    /// Drive the engine clock from the wall clock so good-till-date orders
+    /// expire even when a market is otherwise idle
+    pub async fn tick(&self) {
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let mut registry = self.registry.lock().await;
+        registry.tick(now);
+        self.publish(&mut registry);
+    }
+
+    fn publish(&self, registry: &mut MarketRegistry) {
+        for event in registry.drain_events() {
+            if let Err(e) = self.ws.broadcast(event) {
+                tracing::warn!("Failed to publish engine event: {}", e);
+            }
+        }
+    }
+
+    /// Submit an order; a client order id resubmission gets the original
+    /// outcome, whose order id is the original order's rather than the retry's
+    async fn submit(&self, order: Order) -> Result<SubmitOutcome, Status> {
+        let mut registry = self.registry.lock().await;
+        let outcome = registry.submit(order)?;
+        self.publish(&mut registry);
+        Ok(outcome)
+    }
+
+    async fn cancel(&self, input: CancelOrderRequest, wallet: &str) -> Result<CancelOrderResponse, Status> {
+        let order_id = Uuid::parse_str(&input.order_id)
+            .map_err(|_| Status::invalid_argument("Invalid order ID"))?;
+
+        let mut registry = self.registry.lock().await;
+        let result = registry.cancel(&input.market, order_id, wallet)?;
+        if let Some(error) = result.error() {
+            return Err(error.into());
+        }
+        self.publish(&mut registry);
+
+        Ok(CancelOrderResponse {
+            success: true,
+            order_id: input.order_id,
+        })
+    }
+
+    /// Serve one order-entry session until the client closes it, the stream
+    /// fails or a heartbeat is missed, then pull the wallet's
+    /// cancel-on-disconnect orders unless it reconnects within the grace period
+    async fn run_session(
+        self,
+        authenticated: Option<String>,
+        mut requests: Streaming<SessionRequest>,
+        responses: mpsc::Sender<Result<SessionResponse, Status>>,
+    ) {
+        let timeout = self.session_config.heartbeat_timeout;
+        let logon = match tokio::time::timeout(timeout, requests.message()).await {
+            Ok(Ok(Some(SessionRequest { request: Some(session_request::Request::Logon(logon)) }))) => {
+                acting_wallet(authenticated, &logon.wallet)
+            }
+            _ => Err(Status::failed_precondition("Session must start with a logon")),
+        };
+        let wallet = match logon {
+            Ok(wallet) => wallet,
+            Err(status) => {
+                let _ = responses.send(Err(status)).await;
+                return;
+            }
+        };
+
+        self.sessions.connect(&wallet);
+        tracing::info!("Order session opened for {}", wallet);
+
+        loop {
+            let request = match tokio::time::timeout(timeout, requests.message()).await {
+                Ok(Ok(Some(request))) => request,
+                Ok(Ok(None)) | Ok(Err(_)) => break,
+                Err(_) => {
+                    let _ = responses.send(Err(Status::deadline_exceeded("Heartbeat timed out"))).await;
+                    break;
+                }
+            };
+
+            let response = match request.request {
+                Some(session_request::Request::Heartbeat(heartbeat)) => {
+                    session_response::Response::Heartbeat(heartbeat)
+                }
+                Some(session_request::Request::Submit(input)) => {
+                    let order_id = input.id.clone();
+                    match self.session_submit(&wallet, input).await {
+                        Ok(submitted) => session_response::Response::Submitted(submitted),
+                        Err(status) => session_reject(order_id, status),
+                    }
+                }
+                Some(session_request::Request::Cancel(input)) => {
+                    let order_id = input.order_id.clone();
+                    let cancelled = match acting_wallet(Some(wallet.clone()), &input.wallet) {
+                        Ok(wallet) => self.cancel(input, &wallet).await,
+                        Err(status) => Err(status),
+                    };
+                    match cancelled {
+                        Ok(cancelled) => session_response::Response::Cancelled(cancelled),
+                        Err(status) => session_reject(order_id, status),
+                    }
+                }
+                Some(session_request::Request::Logon(_)) => {
+                    session_reject(String::new(), Status::failed_precondition("Session is already logged on"))
+                }
+                None => session_reject(String::new(), Status::invalid_argument("Request is required")),
+            };
+
+            if responses.send(Ok(SessionResponse { response: Some(response) })).await.is_err() {
+                break;
+            }
+        }
+
+        let generation = self.sessions.disconnect(&wallet);
+        tracing::info!("Order session closed for {}", wallet);
+        tokio::time::sleep(self.session_config.grace_period).await;
+
+        let orders = self.sessions.expire(&wallet, generation);
+        if orders.is_empty() {
+            return;
+        }
+        let mut registry = self.registry.lock().await;
+        let mut cancelled = 0;
+        for (market, order_id) in orders {
+            if let Ok(CancelResult::Cancelled) = registry.cancel(&market, order_id, &wallet) {
+                cancelled += 1;
+            }
+        }
+        self.publish(&mut registry);
+        tracing::info!("Cancelled {} orders of {} on disconnect", cancelled, wallet);
+    }
+
+    async fn session_submit(&self, wallet: &str, input: engine_proto::Order) -> Result<SubmitOrderResponse, Status> {
+        let wallet = acting_wallet(Some(wallet.to_string()), &input.wallet)?;
+        let cancel_on_disconnect = input.cancel_on_disconnect;
+        let order = Order { wallet, ..parse_order(input)? };
+        let (market, wallet) = (order.market.clone(), order.wallet.clone());
+
+        let outcome = self.submit(order).await?;
+        let live = outcome.rested > 0 || outcome.parked > 0;
+        if cancel_on_disconnect && live {
+            let flagged = self.sessions.track(&wallet, &market, outcome.order_id);
+            if flagged >= SESSION_PRUNE_THRESHOLD && flagged.is_power_of_two() {
+                let registry = self.registry.lock().await;
+                self.sessions.retain(&wallet, |market, order_id| {
+                    registry.get_market(market).is_some_and(|engine| engine.owner(order_id).is_some())
+                });
+            }
+        }
+        Ok(to_submit_response(outcome))
+    }
+}
+
+fn session_reject(order_id: String, status: Status) -> session_response::Response {
+    session_response::Response::Rejected(SessionReject {
+        order_id,
+        reason: status.message().to_string(),
+        code: error_code(&status),
+    })
+}
+
+/// Engine errors map to the closest gRPC code, with the machine-readable
+/// code in an `ErrorDetails` message in the status details
+impl From<EngineError> for Status {
+    fn from(error: EngineError) -> Self {
+        let code = match error {
+            EngineError::UnknownMarket | EngineError::OrderNotFound => Code::NotFound,
+            EngineError::MarketExists
+            | EngineError::DuplicateOrderId
+            | EngineError::DuplicateClientOrderId => Code::AlreadyExists,
+            EngineError::NotOwner => Code::PermissionDenied,
+            EngineError::MarketUnavailable(_)
+            | EngineError::AlreadyInState
+            | EngineError::OrderClosed(_)
+            | EngineError::InsufficientBalance
+            | EngineError::MmpNotConfigured
+            | EngineError::KillSwitchEngaged
+            | EngineError::OpenOrderLimit => Code::FailedPrecondition,
+            _ => Code::InvalidArgument,
+        };
+        let details = ErrorDetails { code: error.code().to_string() };
+        Status::with_details(code, error.to_string(), details.encode_to_vec().into())
+    }
+}
+
+/// Machine-readable code of a status raised from an `EngineError`; empty for any other status
+fn error_code(status: &Status) -> String {
+    ErrorDetails::decode(status.details())
+        .map(|details| details.code)
+        .unwrap_or_default()
+}
+
+/// Name of an attribute sent as a proto enum, falling back to its legacy
+/// string field while string clients move over. Enum names lose their prefix
+/// to match the strings (`SIDE_BUY` is `BUY`) and both fields must agree if
+/// both are set. Empty when neither is.
+fn attribute<'a>(
+    from_enum: Option<&'static str>,
+    prefix: &str,
+    legacy: &'a str,
+    invalid: &'static str,
+) -> Result<&'a str, Status> {
+    let from_enum = match from_enum.and_then(|name| name.strip_prefix(prefix)) {
+        Some("UNSPECIFIED") => "",
+        Some(name) => name,
+        None => return Err(Status::invalid_argument(invalid)),
+    };
+
+    match (from_enum, legacy) {
+        ("", legacy) => Ok(legacy),
+        (name, "") => Ok(name),
+        (name, legacy) if name == legacy => Ok(name),
+        _ => Err(Status::invalid_argument(invalid)),
+    }
+}
+
+/// Side of the book, which unlike other attributes has no default
+fn side_name(side_enum: i32, legacy: &str) -> Result<&str, Status> {
+    let from_enum = engine_proto::Side::try_from(side_enum).ok().map(|side| side.as_str_name());
+    attribute(from_enum, "SIDE_", legacy, "Invalid side")
+}
+
+fn parse_side(name: &str) -> Result<Side, Status> {
+    match name {
+        "BUY" => Ok(Side::Buy),
+        "SELL" => Ok(Side::Sell),
+        "" => Err(Status::invalid_argument("Side is required")),
+        _ => Err(Status::invalid_argument("Invalid side")),
+    }
+}
+
+fn side_to_proto(side: Side) -> engine_proto::Side {
+    match side {
+        Side::Buy => engine_proto::Side::Buy,
+        Side::Sell => engine_proto::Side::Sell,
+    }
+}
+
+#[allow(deprecated)] // reads the legacy string field
+fn parse_order_type(input: &engine_proto::Order) -> Result<OrderType, Status> {
+    let from_enum = engine_proto::OrderType::try_from(input.order_type_enum).ok().map(|t| t.as_str_name());
+    match attribute(from_enum, "ORDER_TYPE_", &input.order_type, "Invalid order type")? {
+        "" | "LIMIT" => Ok(OrderType::Limit),
+        "MARKET" => match (input.protection_price, input.max_slippage_bps) {
+            (0, 0) => Ok(OrderType::Market),
+            (price, 0) => Ok(OrderType::ProtectedMarket(Protection::WorstPrice(Price(price)))),
+            (0, bps) => Ok(OrderType::ProtectedMarket(Protection::SlippageBps(bps))),
+            _ => Err(Status::invalid_argument(
+                "Only one of protection_price and max_slippage_bps may be set",
+            )),
+        },
+        "STOP" => Ok(OrderType::Stop { trigger: Price(input.stop_price) }),
+        "STOP_LIMIT" => Ok(OrderType::StopLimit { trigger: Price(input.stop_price) }),
+        _ => Err(Status::invalid_argument("Invalid order type")),
+    }
+}
+
+#[allow(deprecated)] // reads the legacy string field
+fn parse_time_in_force(input: &engine_proto::Order) -> Result<TimeInForce, Status> {
+    let from_enum = engine_proto::TimeInForce::try_from(input.time_in_force_enum).ok().map(|t| t.as_str_name());
+    match attribute(from_enum, "TIME_IN_FORCE_", &input.time_in_force, "Invalid time in force")? {
+        "" | "GTC" => Ok(TimeInForce::Gtc),
+        "IOC" => Ok(TimeInForce::Ioc),
+        "FOK" => Ok(TimeInForce::Fok),
+        "GTD" if input.expire_time > 0 => Ok(TimeInForce::Gtd { expires_at: input.expire_time }),
+        "GTD" => Err(Status::invalid_argument("GTD orders require an expire time")),
+        "POST_ONLY" => Ok(TimeInForce::PostOnly),
+        "POST_ONLY_SLIDE" => Ok(TimeInForce::PostOnlySlide),
+        _ => Err(Status::invalid_argument("Invalid time in force")),
+    }
+}
+
+#[allow(deprecated)] // reads the legacy string field
+fn parse_self_trade_prevention(input: &engine_proto::Order) -> Result<SelfTradePrevention, Status> {
+    let from_enum = engine_proto::SelfTradePrevention::try_from(input.self_trade_prevention_enum)
+        .ok()
+        .map(|mode| mode.as_str_name());
+    let invalid = "Invalid self-trade prevention mode";
+    match attribute(from_enum, "SELF_TRADE_PREVENTION_", &input.self_trade_prevention, invalid)? {
+        "" | "NONE" => Ok(SelfTradePrevention::None),
+        "CANCEL_NEWEST" => Ok(SelfTradePrevention::CancelNewest),
+        "CANCEL_OLDEST" => Ok(SelfTradePrevention::CancelOldest),
+        "CANCEL_BOTH" => Ok(SelfTradePrevention::CancelBoth),
+        "DECREMENT_AND_CANCEL" => Ok(SelfTradePrevention::DecrementAndCancel),
+        _ => Err(Status::invalid_argument("Invalid self-trade prevention mode")),
+    }
+}
+
+#[allow(deprecated)] // reads the legacy side string
+pub(crate) fn parse_order(input: engine_proto::Order) -> Result<Order, Status> {
+    let side = parse_side(side_name(input.side_enum, &input.side)?)?;
+    let order_type = parse_order_type(&input)?;
+    let time_in_force = parse_time_in_force(&input)?;
+    let self_trade_prevention = parse_self_trade_prevention(&input)?;
+
+    let id = match input.id.as_str() {
+        "" => Uuid::new_v4(),
+        id => Uuid::parse_str(id).map_err(|_| Status::invalid_argument("Invalid order ID"))?,
+    };
+
+    Ok(Order {
+        id,
+        market: input.market,
+        wallet: input.wallet,
+        side,
+        price: Price(input.price),
+        quantity: input.quantity,
+        timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        order_type,
+        time_in_force,
+        display_quantity: (input.display_quantity > 0).then_some(input.display_quantity),
+        hidden_quantity: 0,
+        self_trade_prevention,
+        client_order_id: (!input.client_order_id.is_empty()).then_some(input.client_order_id),
+    })
+}
+
+/// The `x-wallet` identity the transport authenticated, if it supplies one
+fn authenticated_wallet<T>(request: &Request<T>) -> Result<Option<String>, Status> {
+    request.metadata()
+        .get("x-wallet")
+        .map(|value| {
+            value.to_str()
+                .map(str::to_string)
+                .map_err(|_| Status::unauthenticated("Invalid wallet identity"))
+        })
+        .transpose()
+}
+
+/// Operator endpoints need the transport to have authenticated one of the
+/// configured operator identities
+fn require_operator<T>(request: &Request<T>, operators: &HashSet<String>) -> Result<(), Status> {
+    match authenticated_wallet(request)? {
+        Some(identity) if operators.contains(&identity) => Ok(()),
+        Some(_) => Err(Status::permission_denied("Operator identity required")),
+        None => Err(Status::unauthenticated("Operator identity required")),
+    }
+}
+
+/// Wallet acting on an existing order: the authenticated identity when there
+/// is one, otherwise the wallet named in the request
+fn acting_wallet(authenticated: Option<String>, claimed: &str) -> Result<String, Status> {
+    match (authenticated, claimed) {
+        (Some(identity), claimed) if !claimed.is_empty() && claimed != identity => {
+            Err(Status::permission_denied("Wallet does not match the authenticated identity"))
+        }
+        (Some(identity), _) => Ok(identity),
+        (None, "") => Err(Status::invalid_argument("Wallet is required")),
+        (None, claimed) => Ok(claimed.to_string()),
+    }
+}
+
+#[allow(deprecated)] // reads the legacy side string
+fn parse_amendment(input: AmendOrderRequest) -> Result<Amendment, Status> {
+    let side = parse_side(side_name(input.side_enum, &input.side)?)?;
+
+    Ok(Amendment {
+        order_id: Uuid::parse_str(&input.order_id)
+            .map_err(|_| Status::invalid_argument("Invalid order ID"))?,
+        market: input.market,
+        wallet: input.wallet,
+        side,
+        price: (input.price > 0).then_some(Price(input.price)),
+        quantity: (input.quantity > 0).then_some(input.quantity),
+        timestamp: chrono::Utc::now().timestamp_millis() as u64,
+    })
+}
+
+fn parse_market_config(input: engine_proto::MarketConfig) -> Result<MarketConfig, Status> {
+    let min_notional = match input.min_notional.as_str() {
+        "" => 0,
+        value => value.parse()
+            .map_err(|_| Status::invalid_argument("Invalid min notional"))?,
+    };
+
+    let matching_mode = match (input.batch_interval_ms, input.batch_arrival.as_str()) {
+        (0, _) => MatchingMode::Continuous,
+        (interval_ms, "" | "RANDOMISED") => MatchingMode::FrequentBatch { interval_ms, arrival: BatchArrival::Randomised },
+        (interval_ms, "SIMULTANEOUS") => MatchingMode::FrequentBatch { interval_ms, arrival: BatchArrival::Simultaneous },
+        _ => return Err(Status::invalid_argument("Invalid batch arrival")),
+    };
+
+    let tiers = input.fee_tiers
+        .into_iter()
+        .map(|tier| {
+            let min_volume = tier.min_volume.parse()
+                .map_err(|_| Status::invalid_argument("Invalid fee tier volume"))?;
+            Ok(FeeTier {
+                min_volume,
+                rates: FeeRates { maker_bps: tier.maker_fee_bps, taker_bps: tier.taker_fee_bps },
+            })
+        })
+        .collect::<Result<_, Status>>()?;
+
+    let price_collar = match (input.collar_bps, input.collar_reference.as_str()) {
+        (0, _) => None,
+        (bps, "" | "LAST_TRADE") => Some(PriceCollar { reference: CollarReference::LastTrade, bps }),
+        (bps, "MID") => Some(PriceCollar { reference: CollarReference::Mid, bps }),
+        _ => return Err(Status::invalid_argument("Invalid collar reference")),
+    };
+    let max_notional = match input.max_order_notional.as_str() {
+        "" | "0" => None,
+        value => Some(MaxNotional(value.parse()
+            .map_err(|_| Status::invalid_argument("Invalid max order notional"))?)),
+    };
+
+    Ok(MarketConfig {
+        market: input.market,
+        base_asset: input.base_asset,
+        quote_asset: input.quote_asset,
+        tick_size: input.tick_size,
+        lot_size: input.lot_size,
+        min_quantity: input.min_quantity,
+        max_quantity: if input.max_quantity == 0 { u64::MAX } else { input.max_quantity },
+        min_notional,
+        price_precision: input.price_precision,
+        matching_mode,
+        fees: FeeSchedule {
+            base: FeeRates { maker_bps: input.maker_fee_bps, taker_bps: input.taker_fee_bps },
+            tiers,
+        },
+        risk: RiskLimits {
+            price_collar,
+            max_quantity: (input.max_order_quantity > 0).then_some(MaxQuantity(input.max_order_quantity)),
+            max_notional,
+            max_open_orders: (input.max_open_orders > 0).then_some(MaxOpenOrders(input.max_open_orders as usize)),
+        },
+    })
+}
+
+fn parse_trading_state(state: &str) -> Result<TradingState, Status> {
+    match state {
+        "PRE_OPEN" => Ok(TradingState::PreOpen),
+        "CONTINUOUS" => Ok(TradingState::Continuous),
+        "HALTED" => Ok(TradingState::Halted),
+        "CANCEL_ONLY" => Ok(TradingState::CancelOnly),
+        "DELISTED" => Ok(TradingState::Delisted),
+        _ => Err(Status::invalid_argument("Invalid trading state")),
+    }
+}
+
+fn to_proto_trade(t: crate::models::trade::Trade) -> Trade {
+    Trade {
+        market: t.market,
+        buy_order: t.buy_order.to_string(),
+        sell_order: t.sell_order.to_string(),
+        price: t.price.0,
+        quantity: t.quantity,
+        sequence: t.sequence,
+        auction_sequence: t.auction_sequence.unwrap_or_default(),
+        taker_side: t.taker_side.map_or(engine_proto::Side::Unspecified, side_to_proto).into(),
+        maker_order: t.maker_order.to_string(),
+        taker_order: t.taker_order.to_string(),
+        maker_wallet: t.maker_wallet,
+        taker_wallet: t.taker_wallet,
+        executed_at: t.executed_at,
+        maker_fee: t.maker_fee.to_string(),
+        taker_fee: t.taker_fee.to_string(),
+        fee_currency: t.fee_currency,
+    }
+}
+
+fn parse_amount(amount: &str) -> Result<u128, Status> {
+    amount.parse().map_err(|_| Status::invalid_argument("Invalid amount"))
+}
+
+fn to_proto_balance(asset: String, balance: crate::engine::ledger::Balance) -> Balance {
+    Balance {
+        asset,
+        available: balance.available.to_string(),
+        locked: balance.locked.to_string(),
+    }
+}
+
+fn to_proto_order_status(report: OrderReport) -> engine_proto::OrderStatus {
+    let record = report.record;
+    engine_proto::OrderStatus {
+        order_id: report.order_id.to_string(),
+        market: report.market,
+        wallet: record.wallet.clone(),
+        side: match record.side {
+            Side::Buy => "BUY".to_string(),
+            Side::Sell => "SELL".to_string(),
+        },
+        side_enum: side_to_proto(record.side).into(),
+        price: record.price.0,
+        quantity: record.quantity,
+        open_quantity: report.open_quantity,
+        filled_quantity: record.filled,
+        average_price: record.average_price().map_or(0, |price| price.0),
+        status: record.status.as_str().to_string(),
+        timestamp: record.timestamp,
+        client_order_id: record.client_order_id.unwrap_or_default(),
+    }
+}
+
+fn to_submit_response(outcome: SubmitOutcome) -> SubmitOrderResponse {
+    let reject_reason = reject_reason(&outcome);
+    SubmitOrderResponse {
+        trades: outcome.trades.into_iter().map(to_proto_trade).collect(),
+        filled_quantity: outcome.filled,
+        rested_quantity: outcome.rested,
+        cancelled_quantity: outcome.cancelled,
+        parked_quantity: outcome.parked,
+        triggered_orders: outcome.triggered.iter().map(Uuid::to_string).collect(),
+        reject_reason,
+        order_id: outcome.order_id.to_string(),
+    }
+}
+
+fn reject_reason(outcome: &SubmitOutcome) -> String {
+    outcome.rejected
+        .map(|reason| reason.as_str().to_string())
+        .unwrap_or_default()
 }
 
 #[tonic::async_trait]
@@ -25,87 +572,281 @@ impl MatchingEngine for GrpcEngine {
         let input = request.into_inner().order
             .ok_or_else(|| Status::invalid_argument("Order is required"))?;
 
-        let order = Order {
-            id: Uuid::parse_str(&input.id)
-                .map_err(|_| Status::invalid_argument("Invalid order ID"))?,
-            market: input.market,
-            wallet: input.wallet,
-            side: if input.side == "BUY" { Side::Buy } else { Side::Sell },
-            price: Price(input.price),
-            quantity: input.quantity,
-            timestamp: chrono::Utc::now().timestamp_millis() as u64,
-        };
+        let order = parse_order(input)?;
+        Ok(Response::new(to_submit_response(self.submit(order).await?)))
+    }
+
+    async fn cancel_order(
+        &self,
+        request: Request<CancelOrderRequest>,
+    ) -> Result<Response<CancelOrderResponse>, Status> {
+        let wallet = acting_wallet(authenticated_wallet(&request)?, &request.get_ref().wallet)?;
+        Ok(Response::new(self.cancel(request.into_inner(), &wallet).await?))
+    }
+
+    async fn replace_order(
+        &self,
+        request: Request<ReplaceOrderRequest>,
+    ) -> Result<Response<ReplaceOrderResponse>, Status> {
+        let claimed = request.get_ref().order.as_ref().map(|o| o.wallet.clone()).unwrap_or_default();
+        let wallet = acting_wallet(authenticated_wallet(&request)?, &claimed)?;
+        let input = request.into_inner().order
+            .ok_or_else(|| Status::invalid_argument("Order is required"))?;
 
-        // Risk validation
-        crate::engine::risk::validate(&order)
-            .map_err(|e| Status::invalid_argument(e))?;
+        let order = Order { wallet, ..parse_order(input)? };
 
         let mut registry = self.registry.lock().await;
-        let trades = registry.submit(order);
-
-        Ok(Response::new(SubmitOrderResponse {
-            trades: trades.into_iter().map(|t| Trade {
-                market: t.market,
-                buy_order: t.buy_order.to_string(),
-                sell_order: t.sell_order.to_string(),
-                price: t.price.0,
-                quantity: t.quantity,
-                sequence: t.sequence,
-            }).collect(),
+        let outcome = registry.replace(order)?;
+        self.publish(&mut registry);
+        let reject_reason = reject_reason(&outcome);
+
+        Ok(Response::new(ReplaceOrderResponse {
+            trades: outcome.trades.into_iter().map(to_proto_trade).collect(),
+            filled_quantity: outcome.filled,
+            rested_quantity: outcome.rested,
+            cancelled_quantity: outcome.cancelled,
+            parked_quantity: outcome.parked,
+            triggered_orders: outcome.triggered.iter().map(Uuid::to_string).collect(),
+            reject_reason,
         }))
     }
 
-    async fn cancel_order(
+    async fn amend_order(
         &self,
-        request: Request<CancelOrderRequest>,
-    ) -> Result<Response<CancelOrderResponse>, Status> {
+        request: Request<AmendOrderRequest>,
+    ) -> Result<Response<AmendOrderResponse>, Status> {
+        let wallet = acting_wallet(authenticated_wallet(&request)?, &request.get_ref().wallet)?;
+        let amendment = Amendment { wallet, ..parse_amendment(request.into_inner())? };
+
+        let mut registry = self.registry.lock().await;
+        let amended = registry.amend(amendment)?;
+        self.publish(&mut registry);
+
+        let outcome = amended.outcome;
+        match outcome.rejected {
+            Some(RejectReason::UnknownOrder) => return Err(EngineError::OrderNotFound.into()),
+            Some(RejectReason::WalletMismatch) => return Err(EngineError::NotOwner.into()),
+            Some(RejectReason::SideMismatch) => return Err(EngineError::SideMismatch.into()),
+            _ => {}
+        }
+        let reject_reason = reject_reason(&outcome);
+
+        Ok(Response::new(AmendOrderResponse {
+            priority_kept: amended.priority_kept,
+            trades: outcome.trades.into_iter().map(to_proto_trade).collect(),
+            filled_quantity: outcome.filled,
+            rested_quantity: outcome.rested,
+            reject_reason,
+            triggered_orders: outcome.triggered.iter().map(Uuid::to_string).collect(),
+            parked_quantity: outcome.parked,
+        }))
+    }
+
+    async fn mass_cancel(
+        &self,
+        request: Request<MassCancelRequest>,
+    ) -> Result<Response<MassCancelResponse>, Status> {
+        let wallet = acting_wallet(authenticated_wallet(&request)?, &request.get_ref().wallet)?;
+        let input = request.into_inner();
+        #[allow(deprecated)] // reads the legacy side string
+        let side = match side_name(input.side_enum, &input.side)? {
+            "" => None,
+            name => Some(parse_side(name)?),
+        };
+        let market = (!input.market.is_empty()).then_some(input.market.as_str());
+
+        let mut registry = self.registry.lock().await;
+        let cancelled = registry.mass_cancel(&wallet, market, side)?;
+        self.publish(&mut registry);
+
+        Ok(Response::new(MassCancelResponse {
+            cancelled_orders: cancelled.iter().map(Uuid::to_string).collect(),
+        }))
+    }
+
+    async fn get_order(
+        &self,
+        request: Request<GetOrderRequest>,
+    ) -> Result<Response<GetOrderResponse>, Status> {
+        let wallet = acting_wallet(authenticated_wallet(&request)?, &request.get_ref().wallet)?;
         let input = request.into_inner();
         let order_id = Uuid::parse_str(&input.order_id)
             .map_err(|_| Status::invalid_argument("Invalid order ID"))?;
 
+        let registry = self.registry.lock().await;
+        let report = registry.order(&input.market, order_id)?
+            .ok_or(EngineError::OrderNotFound)?;
+        if report.record.wallet != wallet {
+            return Err(EngineError::NotOwner.into());
+        }
+
+        Ok(Response::new(GetOrderResponse {
+            order: Some(to_proto_order_status(report)),
+        }))
+    }
+
+    async fn list_open_orders(
+        &self,
+        request: Request<ListOpenOrdersRequest>,
+    ) -> Result<Response<ListOpenOrdersResponse>, Status> {
+        let wallet = acting_wallet(authenticated_wallet(&request)?, &request.get_ref().wallet)?;
+        let input = request.into_inner();
+        let market = (!input.market.is_empty()).then_some(input.market.as_str());
+
+        let registry = self.registry.lock().await;
+        let orders = registry.open_orders(&wallet, market)?;
+
+        Ok(Response::new(ListOpenOrdersResponse {
+            orders: orders.into_iter().map(to_proto_order_status).collect(),
+        }))
+    }
+
+    async fn set_mmp(
+        &self,
+        request: Request<SetMmpRequest>,
+    ) -> Result<Response<SetMmpResponse>, Status> {
+        let wallet = acting_wallet(authenticated_wallet(&request)?, &request.get_ref().wallet)?;
+        let input = request.into_inner();
+        let limits = MmpLimits {
+            window_ms: input.window_ms,
+            quantity: input.quantity_limit,
+            delta: input.delta_limit,
+            fills: input.fill_limit,
+        };
+        let limits = (limits.quantity > 0 || limits.delta > 0 || limits.fills > 0).then_some(limits);
+
         let mut registry = self.registry.lock().await;
-        registry.cancel(&input.market, order_id)
-            .map_err(|e| Status::not_found(e))?;
+        registry.set_mmp(&input.market, &wallet, limits)?;
 
-        Ok(Response::new(CancelOrderResponse {
+        Ok(Response::new(SetMmpResponse {
             success: true,
         }))
     }
 
-    async fn replace_order(
+    async fn reset_mmp(
         &self,
-        request: Request<ReplaceOrderRequest>,
-    ) -> Result<Response<ReplaceOrderResponse>, Status> {
-        let input = request.into_inner().order
-            .ok_or_else(|| Status::invalid_argument("Order is required"))?;
+        request: Request<ResetMmpRequest>,
+    ) -> Result<Response<ResetMmpResponse>, Status> {
+        let wallet = acting_wallet(authenticated_wallet(&request)?, &request.get_ref().wallet)?;
+        let input = request.into_inner();
 
-        let order = Order {
-            id: Uuid::parse_str(&input.id)
-                .map_err(|_| Status::invalid_argument("Invalid order ID"))?,
-            market: input.market,
-            wallet: input.wallet,
-            side: if input.side == "BUY" { Side::Buy } else { Side::Sell },
-            price: Price(input.price),
-            quantity: input.quantity,
-            timestamp: chrono::Utc::now().timestamp_millis() as u64,
-        };
+        let mut registry = self.registry.lock().await;
+        registry.reset_mmp(&input.market, &wallet)?;
+        self.publish(&mut registry);
 
-        crate::engine::risk::validate(&order)
-            .map_err(|e| Status::invalid_argument(e))?;
+        Ok(Response::new(ResetMmpResponse {
+            success: true,
+        }))
+    }
+
+    async fn deposit(
+        &self,
+        request: Request<DepositRequest>,
+    ) -> Result<Response<DepositResponse>, Status> {
+        require_operator(&request, &self.operators)?;
+        let input = request.into_inner();
+        let amount = parse_amount(&input.amount)?;
 
         let mut registry = self.registry.lock().await;
-        let trades = registry.replace(order)
-            .map_err(|e| Status::not_found(e))?;
+        let balance = registry.deposit(&input.wallet, &input.asset, amount, chrono::Utc::now().timestamp_millis() as u64)?;
 
-        Ok(Response::new(ReplaceOrderResponse {
-            trades: trades.into_iter().map(|t| Trade {
-                market: t.market,
-                buy_order: t.buy_order.to_string(),
-                sell_order: t.sell_order.to_string(),
-                price: t.price.0,
-                quantity: t.quantity,
-                sequence: t.sequence,
-            }).collect(),
+        Ok(Response::new(DepositResponse {
+            balance: Some(to_proto_balance(input.asset, balance)),
+        }))
+    }
+
+    async fn withdraw(
+        &self,
+        request: Request<WithdrawRequest>,
+    ) -> Result<Response<WithdrawResponse>, Status> {
+        let wallet = acting_wallet(authenticated_wallet(&request)?, &request.get_ref().wallet)?;
+        let input = request.into_inner();
+        let amount = parse_amount(&input.amount)?;
+
+        let mut registry = self.registry.lock().await;
+        let balance = registry.withdraw(&wallet, &input.asset, amount, chrono::Utc::now().timestamp_millis() as u64)?;
+
+        Ok(Response::new(WithdrawResponse {
+            balance: Some(to_proto_balance(input.asset, balance)),
+        }))
+    }
+
+    async fn get_balances(
+        &self,
+        request: Request<GetBalancesRequest>,
+    ) -> Result<Response<GetBalancesResponse>, Status> {
+        let wallet = acting_wallet(authenticated_wallet(&request)?, &request.get_ref().wallet)?;
+
+        let registry = self.registry.lock().await;
+        let balances = registry.ledger().balances(&wallet);
+
+        Ok(Response::new(GetBalancesResponse {
+            balances: balances.into_iter().map(|(asset, balance)| to_proto_balance(asset, balance)).collect(),
+        }))
+    }
+
+    async fn set_kill_switch(
+        &self,
+        request: Request<SetKillSwitchRequest>,
+    ) -> Result<Response<SetKillSwitchResponse>, Status> {
+        require_operator(&request, &self.operators)?;
+        let input = request.into_inner();
+
+        let mut registry = self.registry.lock().await;
+        let cancelled = registry.set_kill_switch(&input.wallet, input.engaged)?;
+        self.publish(&mut registry);
+
+        Ok(Response::new(SetKillSwitchResponse {
+            cancelled_orders: cancelled.iter().map(Uuid::to_string).collect(),
+        }))
+    }
+
+    async fn register_market(
+        &self,
+        request: Request<RegisterMarketRequest>,
+    ) -> Result<Response<RegisterMarketResponse>, Status> {
+        let input = request.into_inner().config
+            .ok_or_else(|| Status::invalid_argument("Market config is required"))?;
+
+        let config = parse_market_config(input)?;
+
+        let mut registry = self.registry.lock().await;
+        registry.register(config)?;
+
+        Ok(Response::new(RegisterMarketResponse {
+            success: true,
         }))
     }
-}
\ No newline at end of file
+
+    async fn set_market_state(
+        &self,
+        request: Request<SetMarketStateRequest>,
+    ) -> Result<Response<SetMarketStateResponse>, Status> {
+        let input = request.into_inner();
+        let state = parse_trading_state(&input.state)?;
+
+        let mut registry = self.registry.lock().await;
+        let trades = registry.set_state(&input.market, state)?;
+        self.publish(&mut registry);
+
+        Ok(Response::new(SetMarketStateResponse {
+            success: true,
+            trades: trades.into_iter().map(to_proto_trade).collect(),
+        }))
+    }
+
+    type OrderSessionStream = Pin<Box<dyn Stream<Item = Result<SessionResponse, Status>> + Send>>;
+
+    async fn order_session(
+        &self,
+        request: Request<Streaming<SessionRequest>>,
+    ) -> Result<Response<Self::OrderSessionStream>, Status> {
+        let authenticated = authenticated_wallet(&request)?;
+        let (responses, stream) = mpsc::channel(128);
+
+        let session = self.clone();
+        tokio::spawn(session.run_session(authenticated, request.into_inner(), responses));
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(stream))))
+    }
+}
@@ -1,3 +1,4 @@
 pub mod grpc;
+pub mod session;
 pub mod ws;
 
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Timing of order-entry sessions
+#[derive(Debug, Clone, Copy)]
+pub struct SessionConfig {
+    /// A session that sends nothing, heartbeats included, for this long is
+    /// treated as disconnected
+    pub heartbeat_timeout: Duration,
+    /// How long a wallet has to reconnect before its cancel-on-disconnect
+    /// orders are pulled
+    pub grace_period: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_timeout: Duration::from_secs(10),
+            grace_period: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Cancel-on-disconnect orders of one wallet, shared by all its sessions
+#[derive(Default)]
+struct WalletSessions {
+    connected: usize,
+    /// Bumped on every connect and disconnect so a pending grace period can
+    /// tell whether anything happened since it started
+    generation: u64,
+    orders: Vec<(String, Uuid)>,
+}
+
+/// Which wallets have live order-entry sessions and which of their orders to
+/// cancel once they have none
+///
+/// A wallet's orders are only pulled when its last session ends and no new
+/// one arrives within the grace period; a reconnecting session adopts them.
+#[derive(Default)]
+pub struct Sessions {
+    wallets: Mutex<HashMap<String, WalletSessions>>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connect(&self, wallet: &str) {
+        let mut wallets = self.wallets.lock().expect("session lock poisoned");
+        let entry = wallets.entry(wallet.to_string()).or_default();
+        entry.connected += 1;
+        entry.generation += 1;
+    }
+
+    /// Record a session ending, returning the generation to hand to `expire`
+    /// once the grace period is over
+    pub fn disconnect(&self, wallet: &str) -> u64 {
+        let mut wallets = self.wallets.lock().expect("session lock poisoned");
+        let entry = wallets.entry(wallet.to_string()).or_default();
+        entry.connected = entry.connected.saturating_sub(1);
+        entry.generation += 1;
+        entry.generation
+    }
+
+    /// Cancel `order_id` in `market` if the wallet's sessions all drop,
+    /// returning how many orders the wallet now has flagged
+    pub fn track(&self, wallet: &str, market: &str, order_id: Uuid) -> usize {
+        let mut wallets = self.wallets.lock().expect("session lock poisoned");
+        let entry = wallets.entry(wallet.to_string()).or_default();
+        entry.orders.push((market.to_string(), order_id));
+        entry.orders.len()
+    }
+
+    /// Forget flagged orders that are no longer live
+    pub fn retain(&self, wallet: &str, mut live: impl FnMut(&str, Uuid) -> bool) {
+        let mut wallets = self.wallets.lock().expect("session lock poisoned");
+        if let Some(entry) = wallets.get_mut(wallet) {
+            entry.orders.retain(|(market, order_id)| live(market, *order_id));
+        }
+    }
+
+    /// The orders to cancel if nothing has connected or disconnected since
+    /// `generation` and the wallet has no live session; they are forgotten
+    /// once returned
+    pub fn expire(&self, wallet: &str, generation: u64) -> Vec<(String, Uuid)> {
+        let mut wallets = self.wallets.lock().expect("session lock poisoned");
+        match wallets.get(wallet) {
+            Some(entry) if entry.generation == generation && entry.connected == 0 => {
+                wallets.remove(wallet).map(|entry| entry.orders).unwrap_or_default()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
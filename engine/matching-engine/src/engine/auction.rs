@@ -0,0 +1,173 @@
+use crate::engine::orderbook::OrderBook;
+use crate::models::{price::Price, side::Side};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+/// Result of uncrossing the book at a single price
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Uncross {
+    pub price: Price,
+    /// Quantity that executes at `price`
+    pub volume: u64,
+    /// Buy quantity minus sell quantity willing to trade at `price`
+    pub imbalance: i128,
+}
+
+/// Find the price at which the crossed part of the book clears
+///
+/// Every bid and ask price is a candidate. The winner maximises executed
+/// volume, then minimises the imbalance left over, then sits closest to
+/// `reference` (usually the last trade price), and finally is the lowest
+/// remaining candidate. Hidden iceberg quantity takes part in full.
+pub fn clearing_price(book: &OrderBook, reference: Option<Price>) -> Option<Uncross> {
+    clear(|side| book.liquidity(side), reference)
+}
+
+/// Like `clearing_price` but from displayed quantity only, so it is safe to
+/// publish; the actual uncross still counts iceberg reserves.
+pub fn indicative_price(book: &OrderBook, reference: Option<Price>) -> Option<Uncross> {
+    clear(|side| book.levels(side), reference)
+}
+
+fn clear<'a>(
+    depth: impl Fn(Side) -> Box<dyn Iterator<Item = (Price, u64)> + 'a>,
+    reference: Option<Price>,
+) -> Option<Uncross> {
+    let prices: Vec<Price> = depth(Side::Buy)
+        .chain(depth(Side::Sell))
+        .map(|(price, _)| price)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    // Sell quantity priced at or below each candidate
+    let mut supply = Vec::with_capacity(prices.len());
+    let mut asks = depth(Side::Sell).peekable();
+    let mut running = 0u64;
+    for price in &prices {
+        while let Some((_, qty)) = asks.next_if(|(ask, _)| ask <= price) {
+            running = running.saturating_add(qty);
+        }
+        supply.push(running);
+    }
+
+    // Buy quantity priced at or above each candidate
+    let mut demand = vec![0u64; prices.len()];
+    let mut bids = depth(Side::Buy).peekable();
+    let mut running = 0u64;
+    for (i, price) in prices.iter().enumerate().rev() {
+        while let Some((_, qty)) = bids.next_if(|(bid, _)| bid >= price) {
+            running = running.saturating_add(qty);
+        }
+        demand[i] = running;
+    }
+
+    let distance = |price: Price| reference.map(|r| r.0.abs_diff(price.0));
+
+    let mut best: Option<Uncross> = None;
+    for (i, price) in prices.into_iter().enumerate() {
+        let candidate = Uncross {
+            price,
+            volume: demand[i].min(supply[i]),
+            imbalance: demand[i] as i128 - supply[i] as i128,
+        };
+        if candidate.volume == 0 {
+            continue;
+        }
+
+        let better = match best {
+            None => true,
+            Some(current) => {
+                (candidate.volume, std::cmp::Reverse(candidate.imbalance.unsigned_abs()))
+                    .cmp(&(current.volume, std::cmp::Reverse(current.imbalance.unsigned_abs())))
+                    .then_with(|| distance(current.price).cmp(&distance(candidate.price)))
+                    .is_gt()
+            }
+        };
+        if better {
+            best = Some(candidate);
+        }
+    }
+    best
+}
+
+/// Share `volume` between `orders` in proportion to their size, in whole
+/// lots. Lots left over by rounding go one at a time to the orders in the
+/// sequence given.
+pub fn pro_rata(orders: &[(Uuid, u64)], volume: u64, lot_size: u64) -> Vec<(Uuid, u64)> {
+    let total: u128 = orders.iter().map(|(_, qty)| *qty as u128).sum();
+    if total == 0 {
+        return vec![];
+    }
+
+    let lots = (volume / lot_size) as u128;
+    let mut allocations: Vec<(Uuid, u64)> = orders.iter()
+        .map(|(id, qty)| (*id, ((*qty as u128 * lots / total) as u64) * lot_size))
+        .collect();
+
+    let mut left = volume - allocations.iter().map(|(_, qty)| qty).sum::<u64>();
+    while left >= lot_size {
+        let before = left;
+        for ((_, allocated), (_, qty)) in allocations.iter_mut().zip(orders) {
+            if left >= lot_size && *allocated + lot_size <= *qty {
+                *allocated += lot_size;
+                left -= lot_size;
+            }
+        }
+        if left == before {
+            break;
+        }
+    }
+
+    allocations.retain(|(_, qty)| *qty > 0);
+    allocations
+}
+
+/// Walk buy allocations against sell allocations in order. `meet` gets each
+/// buy and sell that come together with what is left of their allocations,
+/// and returns how much of each it used up: the quantity traded, or what
+/// self-trade prevention cancelled.
+pub fn pair(buys: &[(Uuid, u64)], sells: &[(Uuid, u64)], mut meet: impl FnMut(Uuid, Uuid, u64, u64) -> (u64, u64)) {
+    let mut buys = buys.iter().copied();
+    let mut sells = sells.iter().copied();
+    let (mut buy, mut sell) = (buys.next(), sells.next());
+
+    while let (Some((buy_order, buy_left)), Some((sell_order, sell_left))) = (buy, sell) {
+        let (buy_used, sell_used) = meet(buy_order, sell_order, buy_left, sell_left);
+        if buy_used == 0 && sell_used == 0 {
+            break;
+        }
+        buy = if buy_used >= buy_left { buys.next() } else { Some((buy_order, buy_left - buy_used)) };
+        sell = if sell_used >= sell_left { sells.next() } else { Some((sell_order, sell_left - sell_used)) };
+    }
+}
+
+/// Fisher-Yates shuffle of `len` items driven by a splitmix64 sequence from
+/// `seed`; `swap(i, j)` exchanges the items at two positions
+pub fn shuffle(len: usize, seed: u64, mut swap: impl FnMut(usize, usize)) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+
+    for i in (1..len).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        if i != j {
+            swap(i, j);
+        }
+    }
+}
+
+/// Stable seed for a market's `batch`th auction
+pub fn batch_seed(market: &str, batch: u64) -> u64 {
+    // FNV-1a, so the seed does not depend on the std hasher
+    let hash = market.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^ batch
+}
@@ -0,0 +1,81 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::engine::outcome::SubmitOutcome;
+use crate::models::order::Order;
+use serde::{Serialize, Deserialize};
+
+/// How long a client order id stays reserved after it was first used, in
+/// order time (unix millis)
+pub const CLIENT_ORDER_ID_WINDOW_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// A submission made under a client order id, kept so a retry can be
+/// answered with the original result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientSubmission {
+    pub order: Order,
+    pub outcome: SubmitOutcome,
+}
+
+impl ClientSubmission {
+    /// Whether `order` asks for the same thing as the original, ignoring the
+    /// order id a retry may have minted afresh and its arrival time
+    pub fn matches(&self, order: &Order) -> bool {
+        let original = &self.order;
+        original.market == order.market
+            && original.side == order.side
+            && original.price == order.price
+            && original.quantity == order.quantity
+            && original.order_type == order.order_type
+            && original.time_in_force == order.time_in_force
+            && original.display_quantity == order.display_quantity
+            && original.self_trade_prevention == order.self_trade_prevention
+    }
+}
+
+/// Client order ids used per wallet within the dedupe window, aged out by
+/// order timestamp rather than wall clock
+#[derive(Default, Serialize, Deserialize)]
+pub struct ClientOrderIds {
+    by_wallet: HashMap<String, HashMap<String, ClientSubmission>>,
+    /// (wallet, client order id) keyed by the time they were first used
+    used_at: BTreeMap<u64, Vec<(String, String)>>,
+}
+
+impl ClientOrderIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, wallet: &str, client_order_id: &str) -> Option<&ClientSubmission> {
+        self.by_wallet.get(wallet)?.get(client_order_id)
+    }
+
+    pub fn insert(&mut self, client_order_id: String, order: Order, outcome: SubmitOutcome) {
+        let wallet = order.wallet.clone();
+        self.used_at
+            .entry(order.timestamp)
+            .or_default()
+            .push((wallet.clone(), client_order_id.clone()));
+        self.by_wallet
+            .entry(wallet)
+            .or_default()
+            .insert(client_order_id, ClientSubmission { order, outcome });
+    }
+
+    /// Release every id first used a full window before `now`
+    pub fn expire(&mut self, now: u64) {
+        let Some(cutoff) = now.checked_sub(CLIENT_ORDER_ID_WINDOW_MS) else {
+            return;
+        };
+        let kept = self.used_at.split_off(&cutoff.saturating_add(1));
+        let due = std::mem::replace(&mut self.used_at, kept);
+
+        for (wallet, client_order_id) in due.into_values().flatten() {
+            if let Some(ids) = self.by_wallet.get_mut(&wallet) {
+                ids.remove(&client_order_id);
+                if ids.is_empty() {
+                    self.by_wallet.remove(&wallet);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::engine::error::EngineError;
+use crate::engine::fees::FeeSchedule;
+use crate::engine::risk::RiskLimits;
+use crate::models::{order::Order, order_type::OrderType, side::Side};
+use serde::{Serialize, Deserialize};
+
+/// Trading rules for a single market, registered before any order is accepted
+///
+/// Prices and quantities are integers; `price_precision` records how many
+/// decimal places the integer price represents so clients can render it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketConfig {
+    pub market: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    /// Prices must be a multiple of this
+    pub tick_size: u64,
+    /// Quantities must be a multiple of this
+    pub lot_size: u64,
+    pub min_quantity: u64,
+    pub max_quantity: u64,
+    /// Smallest allowed price × quantity for priced orders
+    pub min_notional: u128,
+    pub price_precision: u32,
+    #[serde(default)]
+    pub matching_mode: MatchingMode,
+    #[serde(default)]
+    pub fees: FeeSchedule,
+    #[serde(default)]
+    pub risk: RiskLimits,
+}
+
+/// How a market turns orders into trades
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MatchingMode {
+    /// Orders match on arrival in price-time priority
+    #[default]
+    Continuous,
+    /// Orders are collected for `interval_ms` of engine time and each batch
+    /// clears at a single uniform price
+    FrequentBatch { interval_ms: u64, arrival: BatchArrival },
+}
+
+/// How orders that arrive in the same batch rank against each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchArrival {
+    /// Shuffled before the batch clears, seeded by market and batch number
+    /// so that replaying the log reproduces the same trades
+    Randomised,
+    /// No order is earlier than another: the quantity left over at the
+    /// clearing price is shared out pro rata
+    Simultaneous,
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        Self::new("", "", "")
+    }
+}
+
+impl MarketConfig {
+    /// A config that accepts any positive price and quantity
+    pub fn new(market: &str, base_asset: &str, quote_asset: &str) -> Self {
+        Self {
+            market: market.to_string(),
+            base_asset: base_asset.to_string(),
+            quote_asset: quote_asset.to_string(),
+            tick_size: 1,
+            lot_size: 1,
+            min_quantity: 1,
+            max_quantity: u64::MAX,
+            min_notional: 0,
+            price_precision: 0,
+            matching_mode: MatchingMode::Continuous,
+            fees: FeeSchedule::default(),
+            risk: RiskLimits::default(),
+        }
+    }
+
+    /// Permissive config with the assets taken from a `BASE-QUOTE` symbol
+    pub fn from_symbol(market: &str) -> Self {
+        let (base, quote) = market.split_once('-').unwrap_or((market, ""));
+        Self::new(market, base, quote)
+    }
+
+    /// Check the config itself is usable before it is registered
+    pub fn check(&self) -> Result<(), EngineError> {
+        if self.market.is_empty() {
+            return Err(EngineError::MissingMarketName);
+        }
+        if self.tick_size == 0 {
+            return Err(EngineError::ZeroTickSize);
+        }
+        if self.lot_size == 0 {
+            return Err(EngineError::ZeroLotSize);
+        }
+        if self.min_quantity > self.max_quantity {
+            return Err(EngineError::MinQuantityAboveMax);
+        }
+        if matches!(self.matching_mode, MatchingMode::FrequentBatch { interval_ms: 0, .. }) {
+            return Err(EngineError::ZeroBatchInterval);
+        }
+        self.fees.check()?;
+        self.risk.check()
+    }
+
+    /// Reject orders that break this market's trading rules
+    pub fn validate(&self, order: &Order) -> Result<(), EngineError> {
+        // A stop market buy's price is the cap it reserves funds against
+        let capped = order.side == Side::Buy && matches!(order.order_type, OrderType::Stop { .. });
+        let priced = capped || matches!(order.order_type, OrderType::Limit | OrderType::StopLimit { .. });
+
+        if capped && order.price.0 == 0 {
+            return Err(EngineError::UnpricedStopBuy);
+        }
+        if priced && order.price.0 == 0 {
+            return Err(EngineError::InvalidPrice);
+        }
+        if priced && !order.price.0.is_multiple_of(self.tick_size) {
+            return Err(EngineError::TickViolation);
+        }
+        if order.order_type.stop_price().is_some_and(|stop| !stop.0.is_multiple_of(self.tick_size)) {
+            return Err(EngineError::StopTickViolation);
+        }
+        if !order.quantity.is_multiple_of(self.lot_size) {
+            return Err(EngineError::LotViolation);
+        }
+        if order.display_quantity.is_some_and(|display| !display.is_multiple_of(self.lot_size)) {
+            return Err(EngineError::DisplayLotViolation);
+        }
+        if order.quantity < self.min_quantity {
+            return Err(EngineError::BelowMinQuantity);
+        }
+        if order.quantity > self.max_quantity {
+            return Err(EngineError::AboveMaxQuantity);
+        }
+        if priced && (order.price.0 as u128) * (order.quantity as u128) < self.min_notional {
+            return Err(EngineError::BelowMinNotional);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use crate::engine::outcome::Closed;
+use crate::engine::state::TradingState;
+use thiserror::Error;
+
+/// Why the engine refused a request outright
+///
+/// Business outcomes of an accepted order, such as a post-only order that
+/// would cross, are reported in its `SubmitOutcome` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum EngineError {
+    #[error("Market not found")]
+    UnknownMarket,
+    #[error("Market already registered")]
+    MarketExists,
+    /// The market's trading state does not allow the request
+    #[error("{}", .0.rejection())]
+    MarketUnavailable(TradingState),
+    #[error("Market is already in that state")]
+    AlreadyInState,
+
+    #[error("Market name is required")]
+    MissingMarketName,
+    #[error("Tick size must be positive")]
+    ZeroTickSize,
+    #[error("Lot size must be positive")]
+    ZeroLotSize,
+    #[error("Minimum quantity exceeds maximum quantity")]
+    MinQuantityAboveMax,
+    #[error("Batch interval must be positive")]
+    ZeroBatchInterval,
+    #[error("Fee rates must be within 100% and a maker rebate cannot exceed the taker fee")]
+    InvalidFeeRate,
+    #[error("Fee tiers must be in ascending order of volume")]
+    UnsortedFeeTiers,
+    #[error("Risk limits must be positive")]
+    InvalidRiskLimit,
+
+    #[error("Invalid quantity")]
+    InvalidQuantity,
+    #[error("Invalid price")]
+    InvalidPrice,
+    #[error("Invalid stop price")]
+    InvalidStopPrice,
+    #[error("Invalid display quantity")]
+    InvalidDisplayQuantity,
+    #[error("Market orders cannot be post-only")]
+    PostOnlyMarketOrder,
+    #[error("Market orders cannot be icebergs")]
+    IcebergMarketOrder,
+    #[error("Price is not a multiple of the tick size")]
+    TickViolation,
+    #[error("Stop price is not a multiple of the tick size")]
+    StopTickViolation,
+    #[error("Quantity is not a multiple of the lot size")]
+    LotViolation,
+    #[error("Display quantity is not a multiple of the lot size")]
+    DisplayLotViolation,
+    #[error("Quantity is below the market minimum")]
+    BelowMinQuantity,
+    #[error("Quantity is above the market maximum")]
+    AboveMaxQuantity,
+    #[error("Order value is below the minimum notional")]
+    BelowMinNotional,
+
+    #[error("Order not found")]
+    OrderNotFound,
+    #[error("Order belongs to another wallet")]
+    NotOwner,
+    #[error("Side does not match the order")]
+    SideMismatch,
+    #[error("{}", closed_message(*.0))]
+    OrderClosed(Closed),
+    #[error("Order ID already used")]
+    DuplicateOrderId,
+    #[error("Client order ID already used for a different order")]
+    DuplicateClientOrderId,
+    #[error("Insufficient balance")]
+    InsufficientBalance,
+    #[error("Stop market buys need a price cap to reserve funds against")]
+    UnpricedStopBuy,
+
+    #[error("Wallet is required")]
+    MissingWallet,
+    #[error("Asset is required")]
+    MissingAsset,
+    #[error("Invalid amount")]
+    InvalidAmount,
+
+    #[error("Wallet is blocked by its kill switch")]
+    KillSwitchEngaged,
+    #[error("Price is too far from the reference price")]
+    PriceOutsideCollar,
+    #[error("Quantity is above the risk limit")]
+    OrderQuantityLimit,
+    #[error("Order value is above the risk limit")]
+    OrderNotionalLimit,
+    #[error("Wallet has too many open orders")]
+    OpenOrderLimit,
+
+    #[error("MMP window must be positive")]
+    InvalidMmpWindow,
+    #[error("MMP needs at least one limit")]
+    MissingMmpLimits,
+    #[error("MMP not configured")]
+    MmpNotConfigured,
+}
+
+fn closed_message(closed: Closed) -> &'static str {
+    match closed {
+        Closed::Filled => "Order already filled",
+        Closed::Cancelled => "Order already cancelled",
+        Closed::Expired => "Order already expired",
+    }
+}
+
+impl EngineError {
+    /// Stable machine-readable code for clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::UnknownMarket => "UNKNOWN_MARKET",
+            EngineError::MarketExists => "MARKET_EXISTS",
+            EngineError::MarketUnavailable(TradingState::Halted) => "MARKET_HALTED",
+            EngineError::MarketUnavailable(TradingState::CancelOnly) => "MARKET_CANCEL_ONLY",
+            EngineError::MarketUnavailable(TradingState::Delisted) => "MARKET_DELISTED",
+            EngineError::MarketUnavailable(_) => "MARKET_UNAVAILABLE",
+            EngineError::AlreadyInState => "ALREADY_IN_STATE",
+            EngineError::MissingMarketName => "MISSING_MARKET_NAME",
+            EngineError::ZeroTickSize => "ZERO_TICK_SIZE",
+            EngineError::ZeroLotSize => "ZERO_LOT_SIZE",
+            EngineError::MinQuantityAboveMax => "MIN_QUANTITY_ABOVE_MAX",
+            EngineError::ZeroBatchInterval => "ZERO_BATCH_INTERVAL",
+            EngineError::InvalidFeeRate => "INVALID_FEE_RATE",
+            EngineError::UnsortedFeeTiers => "UNSORTED_FEE_TIERS",
+            EngineError::InvalidRiskLimit => "INVALID_RISK_LIMIT",
+            EngineError::InvalidQuantity => "INVALID_QUANTITY",
+            EngineError::InvalidPrice => "INVALID_PRICE",
+            EngineError::InvalidStopPrice => "INVALID_STOP_PRICE",
+            EngineError::InvalidDisplayQuantity => "INVALID_DISPLAY_QUANTITY",
+            EngineError::PostOnlyMarketOrder => "POST_ONLY_MARKET_ORDER",
+            EngineError::IcebergMarketOrder => "ICEBERG_MARKET_ORDER",
+            EngineError::TickViolation => "TICK_VIOLATION",
+            EngineError::StopTickViolation => "STOP_TICK_VIOLATION",
+            EngineError::LotViolation => "LOT_VIOLATION",
+            EngineError::DisplayLotViolation => "DISPLAY_LOT_VIOLATION",
+            EngineError::BelowMinQuantity => "BELOW_MIN_QUANTITY",
+            EngineError::AboveMaxQuantity => "ABOVE_MAX_QUANTITY",
+            EngineError::BelowMinNotional => "BELOW_MIN_NOTIONAL",
+            EngineError::OrderNotFound => "ORDER_NOT_FOUND",
+            EngineError::NotOwner => "NOT_OWNER",
+            EngineError::SideMismatch => "SIDE_MISMATCH",
+            EngineError::OrderClosed(Closed::Filled) => "ORDER_FILLED",
+            EngineError::OrderClosed(Closed::Cancelled) => "ORDER_CANCELLED",
+            EngineError::OrderClosed(Closed::Expired) => "ORDER_EXPIRED",
+            EngineError::DuplicateOrderId => "DUPLICATE_ORDER_ID",
+            EngineError::DuplicateClientOrderId => "DUPLICATE_CLIENT_ORDER_ID",
+            EngineError::InsufficientBalance => "INSUFFICIENT_BALANCE",
+            EngineError::UnpricedStopBuy => "UNPRICED_STOP_BUY",
+            EngineError::MissingWallet => "MISSING_WALLET",
+            EngineError::MissingAsset => "MISSING_ASSET",
+            EngineError::InvalidAmount => "INVALID_AMOUNT",
+            EngineError::KillSwitchEngaged => "KILL_SWITCH_ENGAGED",
+            EngineError::PriceOutsideCollar => "PRICE_OUTSIDE_COLLAR",
+            EngineError::OrderQuantityLimit => "ORDER_QUANTITY_LIMIT",
+            EngineError::OrderNotionalLimit => "ORDER_NOTIONAL_LIMIT",
+            EngineError::OpenOrderLimit => "OPEN_ORDER_LIMIT",
+            EngineError::InvalidMmpWindow => "INVALID_MMP_WINDOW",
+            EngineError::MissingMmpLimits => "MISSING_MMP_LIMITS",
+            EngineError::MmpNotConfigured => "MMP_NOT_CONFIGURED",
+        }
+    }
+}
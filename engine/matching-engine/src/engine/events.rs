@@ -1,4 +1,6 @@
-use crate::models::trade::Trade;
+use crate::engine::auction::Uncross;
+use crate::engine::state::TradingState;
+use crate::models::{trade::Trade, price::Price, self_trade::SelfTradePrevention};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
@@ -20,15 +22,102 @@ pub enum EngineEvent {
         market: String,
         timestamp: u64,
     },
+    OrderExpired {
+        order_id: Uuid,
+        market: String,
+        timestamp: u64,
+    },
+    /// An iceberg's displayed slice was refilled from its reserve; only the
+    /// displayed quantity is published
+    IcebergRefreshed {
+        order_id: Uuid,
+        market: String,
+        price: Price,
+        displayed_quantity: u64,
+        timestamp: u64,
+    },
+    StopTriggered {
+        order_id: Uuid,
+        market: String,
+        last_trade_price: Price,
+        timestamp: u64,
+    },
+    /// Quantity cancelled instead of trading because both sides belonged to the same wallet
+    SelfTradePrevented {
+        market: String,
+        mode: SelfTradePrevention,
+        taker_order_id: Uuid,
+        maker_order_id: Uuid,
+        taker_cancelled: u64,
+        maker_cancelled: u64,
+        timestamp: u64,
+    },
+    MarketStateChanged {
+        market: String,
+        from: TradingState,
+        to: TradingState,
+        timestamp: u64,
+    },
+    /// Price the book would uncross at if the call ended now; `price` is
+    /// `None` while nothing crosses
+    IndicativePrice {
+        market: String,
+        price: Option<Price>,
+        volume: u64,
+        imbalance: i128,
+        timestamp: u64,
+    },
+    AuctionUncrossed {
+        market: String,
+        auction_sequence: u64,
+        price: Price,
+        volume: u64,
+        timestamp: u64,
+    },
+    OrderAmended {
+        order_id: Uuid,
+        market: String,
+        price: Price,
+        priority_kept: bool,
+        timestamp: u64,
+    },
     OrderReplaced {
         old_order_id: Uuid,
         new_order_id: Uuid,
         market: String,
         timestamp: u64,
     },
+    /// A wallet's fills reached a market maker protection limit; its orders
+    /// in the market were cancelled and new quotes are blocked until reset
+    MmpTriggered {
+        market: String,
+        wallet: String,
+        cancelled_orders: Vec<Uuid>,
+        timestamp: u64,
+    },
+    MmpReset {
+        market: String,
+        wallet: String,
+        timestamp: u64,
+    },
 }
 
 impl EngineEvent {
+    /// Orders whose open quantity the event may have changed
+    pub fn order_ids(&self) -> Vec<Uuid> {
+        match self {
+            EngineEvent::TradeExecuted { trade, .. } => vec![trade.buy_order, trade.sell_order],
+            EngineEvent::OrderCancelled { order_id, .. }
+            | EngineEvent::OrderExpired { order_id, .. }
+            | EngineEvent::StopTriggered { order_id, .. }
+            | EngineEvent::OrderAmended { order_id, .. } => vec![*order_id],
+            EngineEvent::SelfTradePrevented { taker_order_id, maker_order_id, .. } => vec![*taker_order_id, *maker_order_id],
+            EngineEvent::OrderReplaced { old_order_id, new_order_id, .. } => vec![*old_order_id, *new_order_id],
+            EngineEvent::MmpTriggered { cancelled_orders, .. } => cancelled_orders.clone(),
+            _ => vec![],
+        }
+    }
+
     pub fn trade_executed(trade: Trade) -> Self {
         Self::TradeExecuted {
             trade,
@@ -52,6 +141,93 @@ impl EngineEvent {
         }
     }
 
+    /// Expiry is driven by the engine clock, so the event carries that time
+    /// rather than the wall clock to stay identical on replay
+    pub fn order_expired(order_id: Uuid, market: String, timestamp: u64) -> Self {
+        Self::OrderExpired {
+            order_id,
+            market,
+            timestamp,
+        }
+    }
+
+    pub fn iceberg_refreshed(order_id: Uuid, market: String, price: Price, displayed_quantity: u64) -> Self {
+        Self::IcebergRefreshed {
+            order_id,
+            market,
+            price,
+            displayed_quantity,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+
+    pub fn stop_triggered(order_id: Uuid, market: String, last_trade_price: Price) -> Self {
+        Self::StopTriggered {
+            order_id,
+            market,
+            last_trade_price,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+
+    pub fn self_trade_prevented(
+        market: String,
+        mode: SelfTradePrevention,
+        taker_order_id: Uuid,
+        maker_order_id: Uuid,
+        taker_cancelled: u64,
+        maker_cancelled: u64,
+    ) -> Self {
+        Self::SelfTradePrevented {
+            market,
+            mode,
+            taker_order_id,
+            maker_order_id,
+            taker_cancelled,
+            maker_cancelled,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+
+    pub fn market_state_changed(market: String, from: TradingState, to: TradingState) -> Self {
+        Self::MarketStateChanged {
+            market,
+            from,
+            to,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+
+    pub fn indicative_price(market: String, uncross: Option<Uncross>) -> Self {
+        Self::IndicativePrice {
+            market,
+            price: uncross.map(|u| u.price),
+            volume: uncross.map_or(0, |u| u.volume),
+            imbalance: uncross.map_or(0, |u| u.imbalance),
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+
+    pub fn auction_uncrossed(market: String, auction_sequence: u64, price: Price, volume: u64) -> Self {
+        Self::AuctionUncrossed {
+            market,
+            auction_sequence,
+            price,
+            volume,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+
+    pub fn order_amended(order_id: Uuid, market: String, price: Price, priority_kept: bool) -> Self {
+        Self::OrderAmended {
+            order_id,
+            market,
+            price,
+            priority_kept,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+
     pub fn order_replaced(old_order_id: Uuid, new_order_id: Uuid, market: String) -> Self {
         Self::OrderReplaced {
             old_order_id,
@@ -60,4 +236,21 @@ impl EngineEvent {
             timestamp: chrono::Utc::now().timestamp_millis() as u64,
         }
     }
-}
\ No newline at end of file
+
+    pub fn mmp_triggered(market: String, wallet: String, cancelled_orders: Vec<Uuid>) -> Self {
+        Self::MmpTriggered {
+            market,
+            wallet,
+            cancelled_orders,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+
+    pub fn mmp_reset(market: String, wallet: String) -> Self {
+        Self::MmpReset {
+            market,
+            wallet,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        }
+    }
+}
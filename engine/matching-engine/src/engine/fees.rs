@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::engine::error::EngineError;
+use serde::{Serialize, Deserialize};
+
+/// Number of engine days, today included, whose volume counts toward a fee tier
+pub const FEE_VOLUME_WINDOW_DAYS: u64 = 30;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Basis points in the whole of a trade's notional
+const BPS: i32 = 10_000;
+
+/// Fee rates in basis points of notional; a negative maker rate is a rebate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct FeeRates {
+    pub maker_bps: i32,
+    pub taker_bps: i32,
+}
+
+impl FeeRates {
+    /// Fees are at most the notional and takers are never paid
+    fn is_valid(&self) -> bool {
+        (0..=BPS).contains(&self.taker_bps) && self.maker_bps <= BPS
+    }
+}
+
+/// Rates for wallets whose trailing volume has reached `min_volume`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeTier {
+    /// Notional traded in the market over the fee window, in quote units
+    pub min_volume: u128,
+    pub rates: FeeRates,
+}
+
+/// A market's fees, charged in its quote asset
+///
+/// Wallets pay the base rates until their volume reaches a tier; the highest
+/// tier reached replaces them. The default schedule charges nothing.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    pub base: FeeRates,
+    /// In ascending order of volume
+    #[serde(default)]
+    pub tiers: Vec<FeeTier>,
+}
+
+impl FeeSchedule {
+    /// Maker and taker can sit in different tiers, so no maker rebate may
+    /// exceed the lowest taker fee of any tier it could be paid out of
+    pub fn check(&self) -> Result<(), EngineError> {
+        if !self.all_rates().all(FeeRates::is_valid) {
+            return Err(EngineError::InvalidFeeRate);
+        }
+        let lowest_taker = self.all_rates().map(|rates| rates.taker_bps).min().unwrap_or(0);
+        if self.all_rates().any(|rates| rates.maker_bps < -lowest_taker) {
+            return Err(EngineError::InvalidFeeRate);
+        }
+        if !self.tiers.windows(2).all(|pair| pair[0].min_volume < pair[1].min_volume) {
+            return Err(EngineError::UnsortedFeeTiers);
+        }
+        Ok(())
+    }
+
+    /// Highest rate any wallet can be charged, maker or taker; zero if
+    /// every rate is a rebate
+    pub fn max_bps(&self) -> i32 {
+        self.all_rates()
+            .flat_map(|rates| [rates.maker_bps, rates.taker_bps])
+            .fold(0, i32::max)
+    }
+
+    /// The base rates followed by every tier's
+    fn all_rates(&self) -> impl Iterator<Item = &FeeRates> {
+        std::iter::once(&self.base).chain(self.tiers.iter().map(|tier| &tier.rates))
+    }
+
+    /// Rates for a wallet that has traded `volume` over the fee window
+    pub fn rates(&self, volume: u128) -> FeeRates {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| volume >= tier.min_volume)
+            .map_or(self.base, |tier| tier.rates)
+    }
+}
+
+/// Fee on `notional` at `bps`, rounded up so that a rebate rounds toward zero
+pub fn fee(notional: u128, bps: i32) -> i128 {
+    let scaled = i128::try_from(notional).unwrap_or(i128::MAX).saturating_mul(bps as i128);
+    let bps = BPS as i128;
+    scaled.div_euclid(bps) + i128::from(scaled.rem_euclid(bps) > 0)
+}
+
+/// Notional each wallet has traded in a market, bucketed by engine day so
+/// days age out by engine time
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TradingVolumes {
+    by_wallet: HashMap<String, BTreeMap<u64, u128>>,
+}
+
+impl TradingVolumes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Volume of `wallet` over the fee window ending at `now`
+    pub fn volume(&self, wallet: &str, now: u64) -> u128 {
+        self.by_wallet.get(wallet).map_or(0, |days| {
+            days.range(first_day(now)..).fold(0, |total, (_, notional)| total.saturating_add(*notional))
+        })
+    }
+
+    /// Count `notional` traded by `wallet` at `now`, forgetting its days
+    /// that have left the window
+    pub fn record(&mut self, wallet: &str, now: u64, notional: u128) {
+        let days = self.by_wallet.entry(wallet.to_string()).or_default();
+        let today = days.entry(now / DAY_MS).or_default();
+        *today = today.saturating_add(notional);
+        *days = days.split_off(&first_day(now));
+    }
+}
+
+fn first_day(now: u64) -> u64 {
+    (now / DAY_MS + 1).saturating_sub(FEE_VOLUME_WINDOW_DAYS)
+}
@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::engine::error::EngineError;
+use crate::models::trade::Trade;
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+/// A wallet's holding of one asset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Balance {
+    /// Free to withdraw or to back new orders
+    pub available: u128,
+    /// Held for open orders
+    pub locked: u128,
+}
+
+/// What an order must hold per unit of open quantity: quote for a buy, base
+/// for a sell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Escrow {
+    pub asset: String,
+    pub unit: u128,
+}
+
+/// Funds held for one open order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Reservation {
+    wallet: String,
+    asset: String,
+    unit: u128,
+    amount: u128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// A deposit or withdrawal, in the order the ledger applied it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub wallet: String,
+    pub asset: String,
+    pub kind: TransferKind,
+    pub amount: u128,
+    /// Available balance once the transfer was applied
+    pub available: u128,
+    /// Stamped by the caller on the same clock as orders, so replay
+    /// reproduces it
+    pub timestamp: u64,
+}
+
+/// Balances of every wallet across all markets
+///
+/// Open orders hold funds from submission until they fill or leave the book:
+/// buys lock quote and sells lock base. Trades pay out of what the orders
+/// held, fees included, and only deposits and withdrawals move funds in or
+/// out, each recorded in the journal.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    balances: HashMap<String, BTreeMap<String, Balance>>,
+    reservations: HashMap<Uuid, Reservation>,
+    /// Fees taken net of rebates paid, by asset
+    fees: BTreeMap<String, i128>,
+    journal: Vec<JournalEntry>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn balance(&self, wallet: &str, asset: &str) -> Balance {
+        self.balances
+            .get(wallet)
+            .and_then(|assets| assets.get(asset))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Every asset the wallet has held, in name order
+    pub fn balances(&self, wallet: &str) -> Vec<(String, Balance)> {
+        self.balances
+            .get(wallet)
+            .map(|assets| assets.iter().map(|(asset, balance)| (asset.clone(), *balance)).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn fees(&self, asset: &str) -> i128 {
+        self.fees.get(asset).copied().unwrap_or_default()
+    }
+
+    pub fn journal(&self) -> &[JournalEntry] {
+        &self.journal
+    }
+
+    pub fn deposit(&mut self, wallet: &str, asset: &str, amount: u128, timestamp: u64) -> Result<Balance, EngineError> {
+        check_transfer(wallet, asset, amount)?;
+        let balance = balance_mut(&mut self.balances, wallet, asset);
+        balance.available = balance.available.checked_add(amount).ok_or(EngineError::InvalidAmount)?;
+        let balance = *balance;
+        self.record(wallet, asset, TransferKind::Deposit, amount, balance, timestamp);
+        Ok(balance)
+    }
+
+    pub fn withdraw(&mut self, wallet: &str, asset: &str, amount: u128, timestamp: u64) -> Result<Balance, EngineError> {
+        check_transfer(wallet, asset, amount)?;
+        if self.balance(wallet, asset).available < amount {
+            return Err(EngineError::InsufficientBalance);
+        }
+        let balance = balance_mut(&mut self.balances, wallet, asset);
+        balance.available -= amount;
+        let balance = *balance;
+        self.record(wallet, asset, TransferKind::Withdrawal, amount, balance, timestamp);
+        Ok(balance)
+    }
+
+    fn record(&mut self, wallet: &str, asset: &str, kind: TransferKind, amount: u128, balance: Balance, timestamp: u64) {
+        self.journal.push(JournalEntry {
+            sequence: self.journal.len() as u64 + 1,
+            wallet: wallet.to_string(),
+            asset: asset.to_string(),
+            kind,
+            amount,
+            available: balance.available,
+            timestamp,
+        });
+    }
+
+    /// Funds `order_id` holds for `wallet` in `asset`, if any
+    fn held(&self, order_id: Uuid, wallet: &str, asset: &str) -> u128 {
+        self.reservations
+            .get(&order_id)
+            .filter(|held| held.wallet == wallet && held.asset == asset)
+            .map_or(0, |held| held.amount)
+    }
+
+    /// Whether `wallet` can back `quantity` of an order under `escrow`,
+    /// counting what `order_id` already holds
+    pub fn covers(&self, order_id: Uuid, wallet: &str, escrow: &Escrow, quantity: u64) -> bool {
+        let needed = escrow.unit.saturating_mul(quantity as u128);
+        let held = self.held(order_id, wallet, &escrow.asset);
+        self.balance(wallet, &escrow.asset).available.saturating_add(held) >= needed
+    }
+
+    /// Hold funds for `quantity` of an order, replacing whatever it held
+    /// before
+    pub fn reserve(&mut self, order_id: Uuid, wallet: &str, escrow: Escrow, quantity: u64) -> Result<(), EngineError> {
+        if !self.covers(order_id, wallet, &escrow, quantity) {
+            return Err(EngineError::InsufficientBalance);
+        }
+        self.sync(order_id, 0);
+
+        let amount = escrow.unit.saturating_mul(quantity as u128);
+        let balance = balance_mut(&mut self.balances, wallet, &escrow.asset);
+        balance.available -= amount;
+        balance.locked += amount;
+        self.reservations.insert(order_id, Reservation {
+            wallet: wallet.to_string(),
+            asset: escrow.asset,
+            unit: escrow.unit,
+            amount,
+        });
+        Ok(())
+    }
+
+    /// Change what an order holds per unit, once an amend has repriced it;
+    /// the next `sync` moves the difference
+    pub fn reprice(&mut self, order_id: Uuid, unit: u128) {
+        if let Some(held) = self.reservations.get_mut(&order_id) {
+            held.unit = unit;
+        }
+    }
+
+    /// Bring what an order holds in line with its open quantity, releasing
+    /// the excess and forgetting the order once nothing is open
+    pub fn sync(&mut self, order_id: Uuid, open_quantity: u64) {
+        let Some(held) = self.reservations.get_mut(&order_id) else {
+            return;
+        };
+        let target = held.unit.saturating_mul(open_quantity as u128);
+        let balance = balance_mut(&mut self.balances, &held.wallet, &held.asset);
+
+        if held.amount > target {
+            let excess = held.amount - target;
+            balance.locked -= excess;
+            balance.available += excess;
+            held.amount = target;
+        } else {
+            // Only a repriced order needs more, and its amend checked it could pay
+            let topup = (target - held.amount).min(balance.available);
+            balance.available -= topup;
+            balance.locked += topup;
+            held.amount += topup;
+        }
+
+        if open_quantity == 0 {
+            self.reservations.remove(&order_id);
+        }
+    }
+
+    /// Move the funds of a trade: base from seller to buyer and quote the
+    /// other way, each side paying its fee or receiving its rebate in quote
+    pub fn settle(&mut self, trade: &Trade, base: &str, quote: &str) {
+        let notional = trade.price.0 as u128 * trade.quantity as u128;
+        let buyer_is_maker = trade.maker_order == trade.buy_order;
+        let (buyer, seller) = if buyer_is_maker {
+            (&trade.maker_wallet, &trade.taker_wallet)
+        } else {
+            (&trade.taker_wallet, &trade.maker_wallet)
+        };
+        let (buyer_fee, seller_fee) = if buyer_is_maker {
+            (trade.maker_fee, trade.taker_fee)
+        } else {
+            (trade.taker_fee, trade.maker_fee)
+        };
+
+        // Buy orders hold enough per unit to cover their fee as well
+        self.spend(trade.buy_order, buyer, quote, notional.saturating_add(buyer_fee.max(0) as u128));
+        self.credit(buyer, base, trade.quantity as u128);
+        if buyer_fee < 0 {
+            self.credit(buyer, quote, buyer_fee.unsigned_abs());
+        }
+
+        self.spend(trade.sell_order, seller, base, trade.quantity as u128);
+        // A seller's fee never exceeds the proceeds it comes out of
+        let proceeds = if seller_fee < 0 {
+            notional.saturating_add(seller_fee.unsigned_abs())
+        } else {
+            notional.saturating_sub(seller_fee as u128)
+        };
+        self.credit(seller, quote, proceeds);
+
+        let collected = self.fees.entry(quote.to_string()).or_default();
+        *collected = collected.saturating_add(buyer_fee).saturating_add(seller_fee);
+    }
+
+    /// Pay `amount` out of what `order_id` holds, making up any shortfall from
+    /// the wallet's available balance
+    fn spend(&mut self, order_id: Uuid, wallet: &str, asset: &str, amount: u128) {
+        let from_held = match self.reservations.get_mut(&order_id) {
+            Some(held) => {
+                let taken = held.amount.min(amount);
+                held.amount -= taken;
+                taken
+            }
+            None => 0,
+        };
+        let balance = balance_mut(&mut self.balances, wallet, asset);
+        balance.locked -= from_held;
+        // Orders hold what their fills can cost, so a shortfall is an engine bug
+        debug_assert!(balance.available >= amount - from_held, "{wallet} is short of {asset} for a fill");
+        balance.available = balance.available.saturating_sub(amount - from_held);
+    }
+
+    fn credit(&mut self, wallet: &str, asset: &str, amount: u128) {
+        let balance = balance_mut(&mut self.balances, wallet, asset);
+        balance.available = balance.available.saturating_add(amount);
+    }
+}
+
+fn check_transfer(wallet: &str, asset: &str, amount: u128) -> Result<(), EngineError> {
+    if wallet.is_empty() {
+        return Err(EngineError::MissingWallet);
+    }
+    if asset.is_empty() {
+        return Err(EngineError::MissingAsset);
+    }
+    if amount == 0 {
+        return Err(EngineError::InvalidAmount);
+    }
+    Ok(())
+}
+
+fn balance_mut<'a>(balances: &'a mut HashMap<String, BTreeMap<String, Balance>>, wallet: &str, asset: &str) -> &'a mut Balance {
+    balances
+        .entry(wallet.to_string())
+        .or_default()
+        .entry(asset.to_string())
+        .or_default()
+}
@@ -0,0 +1,121 @@
+use crate::engine::outcome::Closed;
+use crate::models::{order::Order, side::Side, price::Price};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+/// How long the record of an order is kept once it has left the book or
+/// been rejected, in engine time (unix millis)
+pub const ORDER_RECORD_RETENTION_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Where an order is in its life
+///
+/// Orders start `New` or are `Rejected` outright, become `PartiallyFilled`
+/// on their first fill, and end `Filled`, `Cancelled` or `Expired`. An order
+/// cancelled after trading stays `Cancelled` with its fills on record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Expired,
+    Rejected,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::New => "NEW",
+            OrderStatus::PartiallyFilled => "PARTIALLY_FILLED",
+            OrderStatus::Filled => "FILLED",
+            OrderStatus::Cancelled => "CANCELLED",
+            OrderStatus::Expired => "EXPIRED",
+            OrderStatus::Rejected => "REJECTED",
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        matches!(self, OrderStatus::New | OrderStatus::PartiallyFilled)
+    }
+
+    /// How the order left the book, if it was ever accepted and has left it
+    pub fn closed(&self) -> Option<Closed> {
+        match self {
+            OrderStatus::Filled => Some(Closed::Filled),
+            OrderStatus::Cancelled => Some(Closed::Cancelled),
+            OrderStatus::Expired => Some(Closed::Expired),
+            OrderStatus::New | OrderStatus::PartiallyFilled | OrderStatus::Rejected => None,
+        }
+    }
+}
+
+impl From<Closed> for OrderStatus {
+    fn from(closed: Closed) -> Self {
+        match closed {
+            Closed::Filled => OrderStatus::Filled,
+            Closed::Cancelled => OrderStatus::Cancelled,
+            Closed::Expired => OrderStatus::Expired,
+        }
+    }
+}
+
+/// The engine's record of an order it has seen, kept after the order leaves the book
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderRecord {
+    pub wallet: String,
+    pub side: Side,
+    pub price: Price,
+    /// Quantity ordered, including any iceberg reserve; an amend changes it
+    /// to the quantity filled so far plus the new open quantity
+    pub quantity: u64,
+    pub timestamp: u64,
+    #[serde(default)]
+    pub client_order_id: Option<String>,
+    pub status: OrderStatus,
+    pub filled: u64,
+    /// Sum of price times quantity over every fill, for the average price
+    notional: u128,
+    /// Engine time the order left the book or was rejected
+    #[serde(default)]
+    pub closed_at: Option<u64>,
+}
+
+impl OrderRecord {
+    pub fn new(order: &Order) -> Self {
+        Self {
+            wallet: order.wallet.clone(),
+            side: order.side,
+            price: order.price,
+            quantity: order.total_quantity(),
+            timestamp: order.timestamp,
+            client_order_id: order.client_order_id.clone(),
+            status: OrderStatus::New,
+            filled: 0,
+            notional: 0,
+            closed_at: None,
+        }
+    }
+
+    pub fn fill(&mut self, price: Price, quantity: u64) {
+        self.filled += quantity;
+        self.notional += price.0 as u128 * quantity as u128;
+        if self.status == OrderStatus::New {
+            self.status = OrderStatus::PartiallyFilled;
+        }
+    }
+
+    /// Volume-weighted fill price, rounded down to a whole price unit
+    pub fn average_price(&self) -> Option<Price> {
+        (self.filled > 0).then(|| Price((self.notional / self.filled as u128) as u64))
+    }
+}
+
+/// An order's record together with what is still open on the book
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderReport {
+    pub order_id: Uuid,
+    pub market: String,
+    pub record: OrderRecord,
+    /// Quantity still resting or waiting to trigger, including iceberg reserve
+    pub open_quantity: u64,
+}
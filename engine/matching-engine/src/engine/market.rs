@@ -1,12 +1,31 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use crate::engine::client_ids::ClientOrderIds;
+use crate::engine::config::MarketConfig;
+use crate::engine::error::EngineError;
+use crate::engine::events::EngineEvent;
+use crate::engine::ledger::{Balance, Ledger};
+use crate::engine::lifecycle::OrderReport;
 use crate::engine::matching::MatchingEngine;
-use crate::models::{order::Order, trade::Trade};
+use crate::engine::mmp::MmpLimits;
+use crate::engine::outcome::{SubmitOutcome, AmendOutcome, CancelResult};
+use crate::engine::risk::{self, RiskContext};
+use crate::engine::state::TradingState;
+use crate::models::{order::Order, amendment::Amendment, trade::Trade, side::Side};
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize)]
 pub struct MarketRegistry {
     markets: HashMap<String, MatchingEngine>,
+    /// Client order ids in their dedupe window; snapshotted so retries
+    /// straddling a restart are still recognised
+    #[serde(default)]
+    client_order_ids: ClientOrderIds,
+    #[serde(default)]
+    ledger: Ledger,
+    /// Wallets whose kill switch is engaged
+    #[serde(default)]
+    kill_switches: HashSet<String>,
 }
 
 impl Default for MarketRegistry {
@@ -17,35 +36,318 @@ impl Default for MarketRegistry {
 
 impl MarketRegistry {
     pub fn new() -> Self {
-        Self { markets: HashMap::new() }
+        Self { markets: HashMap::new(), client_order_ids: ClientOrderIds::new(), ledger: Ledger::new(), kill_switches: HashSet::new() }
     }
 
-    pub fn submit(&mut self, order: Order) -> Vec<Trade> {
+    /// Open a market for trading; orders for unregistered markets are rejected
+    pub fn register(&mut self, config: MarketConfig) -> Result<(), EngineError> {
+        config.check()?;
+        if self.markets.contains_key(&config.market) {
+            return Err(EngineError::MarketExists);
+        }
+
+        self.markets.insert(config.market.clone(), MatchingEngine::with_config(config));
+        Ok(())
+    }
+
+    /// Submit an order. Resubmitting a client order id the wallet used within
+    /// the dedupe window returns the original result if the order is the
+    /// same, and is refused if it is not.
+    pub fn submit(&mut self, order: Order) -> Result<SubmitOutcome, EngineError> {
+        let Some(client_order_id) = order.client_order_id.clone() else {
+            return self.place(order);
+        };
+
+        self.client_order_ids.expire(order.timestamp);
+        if let Some(original) = self.client_order_ids.get(&order.wallet, &client_order_id) {
+            if original.matches(&order) {
+                return Ok(original.outcome.clone());
+            }
+            return Err(EngineError::DuplicateClientOrderId);
+        }
+
+        // Orders the market refuses outright never reserve the id
+        let outcome = self.place(order.clone())?;
+        self.client_order_ids.insert(client_order_id, order, outcome.clone());
+        Ok(outcome)
+    }
+
+    fn place(&mut self, order: Order) -> Result<SubmitOutcome, EngineError> {
         let engine = self.markets
-            .entry(order.market.clone())
-            .or_insert_with(|| MatchingEngine::new(&order.market));
+            .get_mut(&order.market)
+            .ok_or(EngineError::UnknownMarket)?;
 
-        engine.submit(order)
+        if !engine.state.accepts_orders() {
+            return Err(EngineError::MarketUnavailable(engine.state));
+        }
+        if engine.report(order.id).is_some() {
+            return Err(EngineError::DuplicateOrderId);
+        }
+        risk::validate(&order)?;
+        engine.config.validate(&order)?;
+        screen(engine, &self.kill_switches, &order)?;
+
+        let order_id = order.id;
+        let since = engine.pending_events().len();
+        let outcome = reserve(&mut self.ledger, engine, &order).map(|()| engine.submit(order));
+        settle(&mut self.ledger, engine, since, &[order_id]);
+        outcome
     }
 
-    pub fn cancel(&mut self, market: &str, order_id: Uuid) -> Result<(), String> {
+    /// Cancel an order owned by `wallet`; the result says whether it was
+    /// live, belonged to someone else, had already left the book, or was never seen
+    pub fn cancel(&mut self, market: &str, order_id: Uuid, wallet: &str) -> Result<CancelResult, EngineError> {
         let engine = self.markets
             .get_mut(market)
-            .ok_or_else(|| "Market not found".to_string())?;
-        
-        engine.cancel(order_id);
-        Ok(())
+            .ok_or(EngineError::UnknownMarket)?;
+
+        if !engine.state.accepts_cancels() {
+            return Err(EngineError::MarketUnavailable(engine.state));
+        }
+        let since = engine.pending_events().len();
+        let result = engine.cancel(order_id, wallet);
+        settle(&mut self.ledger, engine, since, &[]);
+        Ok(result)
+    }
+
+    /// Cancel every order of `wallet` in one market, or in every market that
+    /// accepts cancels when `market` is `None`, optionally on one side only.
+    /// Returns the ids cancelled, market by market in name order.
+    pub fn mass_cancel(&mut self, wallet: &str, market: Option<&str>, side: Option<Side>) -> Result<Vec<Uuid>, EngineError> {
+        if let Some(market) = market {
+            let engine = self.markets
+                .get_mut(market)
+                .ok_or(EngineError::UnknownMarket)?;
+
+            if !engine.state.accepts_cancels() {
+                return Err(EngineError::MarketUnavailable(engine.state));
+            }
+            let since = engine.pending_events().len();
+            let cancelled = engine.mass_cancel(wallet, side);
+            settle(&mut self.ledger, engine, since, &[]);
+            return Ok(cancelled);
+        }
+
+        let mut markets: Vec<&mut MatchingEngine> = self.markets
+            .values_mut()
+            .filter(|engine| engine.state.accepts_cancels())
+            .collect();
+        markets.sort_by(|a, b| a.market.cmp(&b.market));
+
+        let mut cancelled = vec![];
+        for engine in markets {
+            let since = engine.pending_events().len();
+            cancelled.extend(engine.mass_cancel(wallet, side));
+            settle(&mut self.ledger, engine, since, &[]);
+        }
+        Ok(cancelled)
     }
 
-    pub fn replace(&mut self, order: Order) -> Result<Vec<Trade>, String> {
+    /// Status of an order submitted to `market`; `None` if it never was
+    pub fn order(&self, market: &str, order_id: Uuid) -> Result<Option<OrderReport>, EngineError> {
+        let engine = self.markets
+            .get(market)
+            .ok_or(EngineError::UnknownMarket)?;
+
+        Ok(engine.report(order_id))
+    }
+
+    /// Live orders of `wallet` in one market, or in every market when
+    /// `market` is `None`, market by market in name order
+    pub fn open_orders(&self, wallet: &str, market: Option<&str>) -> Result<Vec<OrderReport>, EngineError> {
+        if let Some(market) = market {
+            let engine = self.markets
+                .get(market)
+                .ok_or(EngineError::UnknownMarket)?;
+            return Ok(engine.open_orders(wallet));
+        }
+
+        let mut markets: Vec<&MatchingEngine> = self.markets.values().collect();
+        markets.sort_by(|a, b| a.market.cmp(&b.market));
+        Ok(markets
+            .into_iter()
+            .flat_map(|engine| engine.open_orders(wallet))
+            .collect())
+    }
+
+    /// Replace an order with one reusing its id; what the original held
+    /// counts toward what the replacement needs
+    pub fn replace(&mut self, order: Order) -> Result<SubmitOutcome, EngineError> {
         let engine = self.markets
             .get_mut(&order.market)
-            .ok_or_else(|| "Market not found".to_string())?;
+            .ok_or(EngineError::UnknownMarket)?;
         
-        Ok(engine.replace(order))
+        if !engine.state.accepts_orders() {
+            return Err(EngineError::MarketUnavailable(engine.state));
+        }
+        risk::validate(&order)?;
+        engine.config.validate(&order)?;
+        screen(engine, &self.kill_switches, &order)?;
+        engine.replaceable(order.id, &order.wallet)?;
+
+        let order_id = order.id;
+        let since = engine.pending_events().len();
+        let outcome = reserve(&mut self.ledger, engine, &order).and_then(|()| engine.replace(order));
+        settle(&mut self.ledger, engine, since, &[order_id]);
+        outcome
+    }
+
+    pub fn amend(&mut self, amendment: Amendment) -> Result<AmendOutcome, EngineError> {
+        let engine = self.markets
+            .get_mut(&amendment.market)
+            .ok_or(EngineError::UnknownMarket)?;
+
+        if !engine.state.accepts_orders() {
+            return Err(EngineError::MarketUnavailable(engine.state));
+        }
+
+        let order_id = amendment.order_id;
+        let mut escrow = None;
+        if let Some(current) = engine.orderbook.get(order_id).or_else(|| engine.stops.get(order_id)) {
+            let amended = amendment.apply(current);
+            risk::validate(&amended)?;
+            engine.config.validate(&amended)?;
+            screen(engine, &self.kill_switches, &amended)?;
+            if current.wallet == amendment.wallet && current.side == amendment.side {
+                let needed = engine.escrow(&amended)?;
+                if !self.ledger.covers(order_id, &amended.wallet, &needed, amended.total_quantity()) {
+                    return Err(EngineError::InsufficientBalance);
+                }
+                escrow = Some(needed);
+            }
+        }
+
+        let since = engine.pending_events().len();
+        let outcome = engine.amend(amendment);
+        if let (None, Some(escrow)) = (outcome.outcome.rejected, escrow) {
+            self.ledger.reprice(order_id, escrow.unit);
+        }
+        settle(&mut self.ledger, engine, since, &[order_id]);
+        Ok(outcome)
+    }
+
+    /// Configure a wallet's market maker protection in one market, or remove
+    /// it with `None`
+    pub fn set_mmp(&mut self, market: &str, wallet: &str, limits: Option<MmpLimits>) -> Result<(), EngineError> {
+        let engine = self.markets
+            .get_mut(market)
+            .ok_or(EngineError::UnknownMarket)?;
+
+        if let Some(limits) = &limits {
+            limits.check()?;
+        }
+        engine.set_mmp(wallet, limits);
+        Ok(())
+    }
+
+    /// Unblock a wallet whose market maker protection triggered
+    pub fn reset_mmp(&mut self, market: &str, wallet: &str) -> Result<(), EngineError> {
+        let engine = self.markets
+            .get_mut(market)
+            .ok_or(EngineError::UnknownMarket)?;
+
+        if !engine.reset_mmp(wallet) {
+            return Err(EngineError::MmpNotConfigured);
+        }
+        Ok(())
+    }
+
+    /// Change a market's trading state, returning the trades of any auction
+    /// uncross this causes
+    pub fn set_state(&mut self, market: &str, state: TradingState) -> Result<Vec<Trade>, EngineError> {
+        let engine = self.markets
+            .get_mut(market)
+            .ok_or(EngineError::UnknownMarket)?;
+
+        let since = engine.pending_events().len();
+        let trades = engine.set_state(state);
+        settle(&mut self.ledger, engine, since, &[]);
+        trades
+    }
+
+    /// Advance every market's clock, expiring good-till-date orders that are due
+    pub fn tick(&mut self, now: u64) -> Vec<Uuid> {
+        let mut expired = vec![];
+        for engine in self.markets.values_mut() {
+            let since = engine.pending_events().len();
+            expired.extend(engine.tick(now));
+            settle(&mut self.ledger, engine, since, &[]);
+        }
+        expired
+    }
+
+    /// Engage or release a wallet's kill switch. Engaging it cancels the
+    /// wallet's orders in every market that accepts cancels and refuses its
+    /// new orders, amends and replaces until released; returns the ids cancelled.
+    pub fn set_kill_switch(&mut self, wallet: &str, engaged: bool) -> Result<Vec<Uuid>, EngineError> {
+        if wallet.is_empty() {
+            return Err(EngineError::MissingWallet);
+        }
+        if !engaged {
+            self.kill_switches.remove(wallet);
+            return Ok(vec![]);
+        }
+        self.kill_switches.insert(wallet.to_string());
+        self.mass_cancel(wallet, None, None)
+    }
+
+    pub fn deposit(&mut self, wallet: &str, asset: &str, amount: u128, timestamp: u64) -> Result<Balance, EngineError> {
+        self.ledger.deposit(wallet, asset, amount, timestamp)
+    }
+
+    /// Withdraw from the available balance; funds held for open orders stay put
+    pub fn withdraw(&mut self, wallet: &str, asset: &str, amount: u128, timestamp: u64) -> Result<Balance, EngineError> {
+        self.ledger.withdraw(wallet, asset, amount, timestamp)
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub fn drain_events(&mut self) -> Vec<EngineEvent> {
+        self.markets
+            .values_mut()
+            .flat_map(|engine| engine.drain_events())
+            .collect()
     }
 
     pub fn get_market(&self, market: &str) -> Option<&MatchingEngine> {
         self.markets.get(market)
     }
-}
\ No newline at end of file
+}
+
+/// Run the market's pre-trade risk checks on `order`
+fn screen(engine: &MatchingEngine, kill_switches: &HashSet<String>, order: &Order) -> Result<(), EngineError> {
+    // An order already on the book is being amended or replaced, not added
+    let existing = engine.owner(order.id) == Some(order.wallet.as_str());
+    let context = RiskContext {
+        engine,
+        killed: kill_switches.contains(&order.wallet),
+        open_orders: engine.open_order_count(&order.wallet) - usize::from(existing),
+    };
+    risk::run(engine.config.risk.checks(), order, &context)
+}
+
+/// Hold the funds `order` needs before the engine sees it. The market's clock
+/// is advanced first so a market buy is priced against the book it will meet.
+fn reserve(ledger: &mut Ledger, engine: &mut MatchingEngine, order: &Order) -> Result<(), EngineError> {
+    engine.tick(order.timestamp);
+    let escrow = engine.escrow(order)?;
+    ledger.reserve(order.id, &order.wallet, escrow, order.total_quantity())
+}
+
+/// Apply to the ledger whatever `engine` did since it held `since` pending
+/// events: pay out its trades, then release what the orders they touched, and
+/// `order_ids`, no longer need
+fn settle(ledger: &mut Ledger, engine: &MatchingEngine, since: usize, order_ids: &[Uuid]) {
+    let mut touched = order_ids.to_vec();
+    for event in &engine.pending_events()[since..] {
+        if let EngineEvent::TradeExecuted { trade, .. } = event {
+            ledger.settle(trade, &engine.config.base_asset, &engine.config.quote_asset);
+        }
+        touched.extend(event.order_ids());
+    }
+    for order_id in touched {
+        ledger.sync(order_id, engine.open_quantity(order_id));
+    }
+}
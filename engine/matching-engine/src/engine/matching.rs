@@ -1,135 +1,1065 @@
-use crate::engine::orderbook::OrderBook;
-use crate::models::{order::Order, trade::Trade, side::Side, price::Price};
+use crate::engine::auction::{self, Uncross};
+use crate::engine::config::{MarketConfig, MatchingMode, BatchArrival};
+use crate::engine::error::EngineError;
+use crate::engine::events::EngineEvent;
+use crate::engine::fees::{self, FeeRates, TradingVolumes};
+use crate::engine::ledger::Escrow;
+use crate::engine::lifecycle::{OrderRecord, OrderReport, OrderStatus, ORDER_RECORD_RETENTION_MS};
+use crate::engine::mmp::{MmpLimits, MmpState};
+use crate::engine::orderbook::{OrderBook, Fill};
+use crate::engine::outcome::{SubmitOutcome, AmendOutcome, RejectReason, CancelResult, Closed};
+use crate::engine::state::TradingState;
+use crate::engine::stops::TriggerBook;
+use crate::models::{order::Order, amendment::Amendment, order_type::OrderType, time_in_force::TimeInForce, self_trade::SelfTradePrevention, trade::Trade, side::Side, price::Price};
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Serialize, Deserialize)]
 pub struct MatchingEngine {
     pub market: String,
+    #[serde(default)]
+    pub config: MarketConfig,
+    #[serde(default)]
+    pub state: TradingState,
     pub orderbook: OrderBook,
     pub sequence: u64,
+    /// Stop orders waiting for the last trade price to reach their trigger
+    #[serde(default)]
+    pub stops: TriggerBook,
+    #[serde(default)]
+    pub last_trade_price: Option<Price>,
+    /// Number of auctions uncrossed so far; shared by every trade of an uncross
+    #[serde(default)]
+    pub auction_sequence: u64,
+    /// Engine time in unix millis; only ever moves forward, driven by order
+    /// timestamps and `tick` so that replaying the same input expires the same orders
+    #[serde(default)]
+    pub clock: u64,
+    /// Good-till-date orders keyed by expiry time
+    #[serde(default)]
+    expiries: BTreeMap<u64, Vec<Uuid>>,
+    /// Every order submitted here and what became of it, kept for a while
+    /// after it leaves the book so status queries and late cancels can say why
+    #[serde(default)]
+    records: HashMap<Uuid, OrderRecord>,
+    /// Orders that left the book or were rejected, keyed by the engine time
+    /// they did, so their records can be dropped once retention runs out
+    #[serde(default)]
+    closed: BTreeMap<u64, Vec<Uuid>>,
+    /// Engine time at which the open batch clears, in frequent batch mode
+    #[serde(default)]
+    next_batch: u64,
+    /// Orders collected into the open batch, in arrival order
+    #[serde(default)]
+    batch: Vec<Uuid>,
+    /// Market maker protection by wallet
+    #[serde(default)]
+    mmp: HashMap<String, MmpState>,
+    /// Traded notional by wallet, for fee tiers
+    #[serde(default)]
+    volumes: TradingVolumes,
+    #[serde(skip)]
+    events: Vec<EngineEvent>,
 }
 
 impl MatchingEngine {
+    /// Engine with permissive trading rules, mostly useful in tests
     pub fn new(market: &str) -> Self {
+        Self::with_config(MarketConfig::from_symbol(market))
+    }
+
+    pub fn with_config(config: MarketConfig) -> Self {
         Self {
-            market: market.to_string(),
+            market: config.market.clone(),
+            config,
+            state: TradingState::Continuous,
             orderbook: OrderBook::new(),
             sequence: 0,
+            stops: TriggerBook::new(),
+            last_trade_price: None,
+            auction_sequence: 0,
+            clock: 0,
+            expiries: BTreeMap::new(),
+            records: HashMap::new(),
+            closed: BTreeMap::new(),
+            next_batch: 0,
+            batch: vec![],
+            mmp: HashMap::new(),
+            volumes: TradingVolumes::new(),
+            events: vec![],
         }
     }
-    pub fn cancel(&mut self, order_id: Uuid) {
-        if let Some((price, side)) = self.orderbook.index.remove(&order_id) {
-            let book = if side == Side::Buy {
-                &mut self.orderbook.bids
-            } else {
-                &mut self.orderbook.asks
-            };
-    
-            if let Some(queue) = book.get_mut(&price) {
-                queue.retain(|o| o.id != order_id);
+
+    /// Advance the engine clock to `now`, expire every good-till-date order
+    /// whose expiry time has been reached and, in frequent batch mode, clear
+    /// the open batch once its interval is over
+    pub fn tick(&mut self, now: u64) -> Vec<Uuid> {
+        if now <= self.clock {
+            return vec![];
+        }
+        self.clock = now;
+
+        let mut expired = vec![];
+        if let MatchingMode::FrequentBatch { interval_ms, .. } = self.config.matching_mode {
+            if self.state == TradingState::Continuous && self.next_batch <= now {
+                // Orders that expired before the batch closed take no part in it
+                expired = self.expire(self.next_batch);
+                self.clear_batch();
+                self.next_batch = now - now % interval_ms + interval_ms;
             }
         }
+        expired.extend(self.expire(now));
+        self.forget_closed(now);
+        expired
     }
-    pub fn replace(&mut self, order: Order) -> Vec<Trade> {
-        self.cancel(order.id);
-        self.submit(order)
-    }    
 
-    pub fn submit(&mut self, mut order: Order) -> Vec<Trade> {
+    /// Drop the records of orders closed a full retention window before `now`
+    fn forget_closed(&mut self, now: u64) {
+        let Some(cutoff) = now.checked_sub(ORDER_RECORD_RETENTION_MS) else {
+            return;
+        };
+        let kept = self.closed.split_off(&cutoff.saturating_add(1));
+        let due = std::mem::replace(&mut self.closed, kept);
+
+        for (closed_at, order_ids) in due {
+            for order_id in order_ids {
+                // A replace reuses the id, so only drop the record this entry was for
+                if self.records.get(&order_id).is_some_and(|record| record.closed_at == Some(closed_at)) {
+                    self.records.remove(&order_id);
+                }
+            }
+        }
+    }
+
+    fn expire(&mut self, now: u64) -> Vec<Uuid> {
+        let pending = self.expiries.split_off(&now.saturating_add(1));
+        let due = std::mem::replace(&mut self.expiries, pending);
+
+        let mut expired = vec![];
+        for (expires_at, order_ids) in due {
+            for order_id in order_ids {
+                // Orders filled or cancelled before expiring are no longer on
+                // either book, and a replace may have reused the id for an
+                // order with another expiry
+                let due = self.orderbook
+                    .get(order_id)
+                    .or_else(|| self.stops.get(order_id))
+                    .is_some_and(|o| o.time_in_force.expires_at() == Some(expires_at));
+                if due && self.remove(order_id).is_some() {
+                    self.events.push(EngineEvent::order_expired(order_id, self.market.clone(), expires_at));
+                    self.close(order_id, Closed::Expired);
+                    expired.push(order_id);
+                }
+            }
+        }
+        expired
+    }
+
+    /// Move the market to a new trading state, returning any trades this
+    /// causes. Entering continuous trading uncrosses the book collected during
+    /// the call period; delisting cancels everything left on the book.
+    pub fn set_state(&mut self, state: TradingState) -> Result<Vec<Trade>, EngineError> {
+        self.state.transition(state)?;
+
+        let previous = std::mem::replace(&mut self.state, state);
+        self.events.push(EngineEvent::market_state_changed(self.market.clone(), previous, state));
+
         let mut trades = vec![];
+        if state == TradingState::Continuous {
+            trades = self.uncross();
+            self.run_triggers(&mut trades);
+        }
 
-        match order.side {
-            Side::Buy => {
-                while order.quantity > 0 {
-                    // Get best price and check if we can match
-                    let best_price = {
-                        let (price, asks) = match self.orderbook.best_ask() {
-                            Some(v) => v,
-                            None => break,
-                        };
-                        
-                        if order.price < *price {
-                            break;
-                        }
-                        *price
-                    };
-
-                    // Now we can safely borrow mutably again
-                    let (_, asks) = self.orderbook.best_ask().unwrap();
-                    let mut resting = asks.pop_front().unwrap();
-                    let qty = order.quantity.min(resting.quantity);
-
-                    order.quantity -= qty;
-                    resting.quantity -= qty;
-
-                    // Create trade (borrow ended, so we can access self)
-                    self.sequence += 1;
-                    let trade = Trade {
-                        market: self.market.clone(),
-                        buy_order: order.id,
-                        sell_order: resting.id,
-                        price: best_price,
-                        quantity: qty,
-                        sequence: self.sequence,
-                    };
-                    trades.push(trade);
-
-                    if resting.quantity > 0 {
-                        asks.push_front(resting);
-                    }
+        if state == TradingState::Delisted {
+            let resting: Vec<Uuid> = self.orderbook.orders(Side::Buy)
+                .chain(self.orderbook.orders(Side::Sell))
+                .chain(self.stops.buys.values().flatten())
+                .chain(self.stops.sells.values().flatten())
+                .map(|o| o.id)
+                .collect();
+            for order_id in resting {
+                self.withdraw(order_id);
+            }
+        }
+        Ok(trades)
+    }
+
+    /// Price, volume and imbalance the displayed book would uncross at right
+    /// now; iceberg reserves are left out so publishing it leaks nothing
+    pub fn indicative_uncross(&self) -> Option<Uncross> {
+        auction::indicative_price(&self.orderbook, self.last_trade_price)
+    }
+
+    /// Execute the crossed part of the book at a single clearing price
+    fn uncross(&mut self) -> Vec<Trade> {
+        let Some(uncross) = auction::clearing_price(&self.orderbook, self.last_trade_price) else {
+            return vec![];
+        };
+
+        self.auction_sequence += 1;
+        let auction = self.auction_sequence;
+        let buys = self.allocate(Side::Buy, uncross);
+        let sells = self.allocate(Side::Sell, uncross);
+
+        let mut trades = vec![];
+        let mut fills = vec![];
+        auction::pair(&buys, &sells, |buy_order, sell_order, buy_left, sell_left| {
+            if let Some(cancelled) = self.prevent_auction_self_trade(buy_order, sell_order) {
+                return cancelled;
+            }
+
+            let qty = buy_left.min(sell_left);
+            let buy = self.party(buy_order);
+            let sell = self.party(sell_order);
+            for order_id in [buy_order, sell_order] {
+                if self.orderbook.reduce(order_id, qty) == Some(0) {
+                    self.close(order_id, Closed::Filled);
+                }
+            }
+            fills.push((buy.wallet.clone(), Side::Buy, qty));
+            fills.push((sell.wallet.clone(), Side::Sell, qty));
+            trades.push(self.record_trade(buy, sell, None, uncross.price, qty, Some(auction)));
+            (qty, qty)
+        });
+
+        self.events.push(EngineEvent::auction_uncrossed(self.market.clone(), auction, uncross.price, uncross.volume));
+        // Every order in an uncross was resting, so each fill counts towards
+        // protection; counted only once the auction is done so that pulling
+        // quotes cannot break pairs it already made
+        for (wallet, side, qty) in fills {
+            self.record_mmp_fill(&wallet, side, qty);
+        }
+        trades
+    }
+
+    /// Apply self-trade prevention when a buy and a sell of one wallet meet in
+    /// an uncross, returning what it cancelled from each. Nothing arrives
+    /// mid-auction, so the newer order (the buy on a tie) stands in for the
+    /// taker and its mode applies.
+    fn prevent_auction_self_trade(&mut self, buy_order: Uuid, sell_order: Uuid) -> Option<(u64, u64)> {
+        let buy = self.orderbook.get(buy_order)?;
+        let sell = self.orderbook.get(sell_order)?;
+        if buy.wallet != sell.wallet {
+            return None;
+        }
+        let buy_newest = buy.timestamp >= sell.timestamp;
+        let (taker, maker) = if buy_newest { (buy, sell) } else { (sell, buy) };
+        let mode = taker.self_trade_prevention;
+        if mode == SelfTradePrevention::None {
+            return None;
+        }
+
+        let (taker_cancelled, maker_cancelled) = mode.cancellations(taker.total_quantity(), maker.total_quantity());
+        let (taker_id, maker_id) = (taker.id, maker.id);
+        for (order_id, cancelled) in [(taker_id, taker_cancelled), (maker_id, maker_cancelled)] {
+            if cancelled > 0 && self.orderbook.reduce(order_id, cancelled) == Some(0) {
+                self.close(order_id, Closed::Cancelled);
+            }
+        }
+        self.events.push(EngineEvent::self_trade_prevented(
+            self.market.clone(),
+            mode,
+            taker_id,
+            maker_id,
+            taker_cancelled,
+            maker_cancelled,
+        ));
+
+        Some(if buy_newest { (taker_cancelled, maker_cancelled) } else { (maker_cancelled, taker_cancelled) })
+    }
+
+    /// Quantity each order on `side` gets in the uncross, in priority order.
+    /// Levels better than the clearing price fill in full; at the marginal
+    /// level orders fill in time priority, or pro rata when the batch treats
+    /// arrivals as simultaneous.
+    fn allocate(&self, side: Side, uncross: Uncross) -> Vec<(Uuid, u64)> {
+        let simultaneous = matches!(
+            self.config.matching_mode,
+            MatchingMode::FrequentBatch { arrival: BatchArrival::Simultaneous, .. }
+        );
+        let mut orders = self.orderbook.orders(side)
+            .take_while(|o| crosses(side, Some(o.price), uncross.price))
+            .peekable();
+
+        let mut remaining = uncross.volume;
+        let mut allocations = vec![];
+        while remaining > 0 {
+            let Some(price) = orders.peek().map(|o| o.price) else {
+                break;
+            };
+            let level: Vec<(Uuid, u64)> = std::iter::from_fn(|| orders.next_if(|o| o.price == price))
+                .map(|o| (o.id, o.total_quantity()))
+                .collect();
+
+            let size: u64 = level.iter().map(|(_, qty)| qty).sum();
+            if simultaneous && size > remaining {
+                allocations.extend(auction::pro_rata(&level, remaining, self.config.lot_size));
+                break;
+            }
+            for (order_id, qty) in level {
+                let qty = qty.min(remaining);
+                if qty == 0 {
+                    break;
                 }
+                allocations.push((order_id, qty));
+                remaining -= qty;
+            }
+        }
+        allocations
+    }
+
+    fn batching(&self) -> bool {
+        matches!(self.config.matching_mode, MatchingMode::FrequentBatch { .. })
+    }
+
+    /// Clear the open batch at its uniform price. Stops it triggers join the next batch.
+    fn clear_batch(&mut self) {
+        let batch: HashSet<Uuid> = std::mem::take(&mut self.batch).into_iter().collect();
+        if matches!(
+            self.config.matching_mode,
+            MatchingMode::FrequentBatch { arrival: BatchArrival::Randomised, .. }
+        ) {
+            self.shuffle_arrivals(&batch);
+        }
+
+        let mut trades = self.uncross();
+        self.run_triggers(&mut trades);
+    }
+
+    /// Randomise the queue positions held by this batch's orders at every
+    /// level; orders left over from earlier batches keep their priority
+    fn shuffle_arrivals(&mut self, batch: &HashSet<Uuid>) {
+        let seed = auction::batch_seed(&self.market, self.auction_sequence + 1);
+        for side in [Side::Buy, Side::Sell] {
+            let arrivals: Vec<(Price, Uuid)> = self.orderbook.orders(side)
+                .filter(|o| batch.contains(&o.id))
+                .map(|o| (o.price, o.id))
+                .collect();
+
+            for level in arrivals.chunk_by(|a, b| a.0 == b.0) {
+                let mut ids: Vec<Uuid> = level.iter().map(|(_, id)| *id).collect();
+                auction::shuffle(ids.len(), seed ^ level[0].0 .0, |i, j| {
+                    self.orderbook.swap(ids[i], ids[j]);
+                    ids.swap(i, j);
+                });
             }
+        }
+    }
+
+    fn publish_indicative(&mut self) {
+        let uncross = self.indicative_uncross();
+        self.events.push(EngineEvent::indicative_price(self.market.clone(), uncross));
+    }
+
+    /// Events emitted since the last call, oldest first
+    pub fn drain_events(&mut self) -> Vec<EngineEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Events emitted since the last drain, left in place
+    pub fn pending_events(&self) -> &[EngineEvent] {
+        &self.events
+    }
+
+    /// What `order` must hold per unit while open. Buys hold their worst
+    /// price plus the highest fee they could pay on it, rounded up per unit
+    /// so that fees rounded per fill never exceed it. A market buy's worst
+    /// price is the deepest level of the book it could reach, so it must
+    /// arrive after the clock has been advanced. Stop buys only trigger
+    /// later, so they hold against the price cap they carry.
+    pub fn escrow(&self, order: &Order) -> Result<Escrow, EngineError> {
+        if order.side == Side::Sell {
+            return Ok(Escrow { asset: self.config.base_asset.clone(), unit: 1 });
+        }
+
+        let price = match order.order_type {
+            OrderType::Limit | OrderType::StopLimit { .. } => order.price,
+            OrderType::Stop { .. } if order.price.0 == 0 => return Err(EngineError::UnpricedStopBuy),
+            OrderType::Stop { .. } => order.price,
+            OrderType::Market | OrderType::ProtectedMarket(_) => {
+                let reachable = self.reachable_price(order);
+                match self.limit_price(order) {
+                    Some(limit) => reachable.min(limit),
+                    None => reachable,
+                }
+            }
+        };
+        let fee = fees::fee(price.0 as u128, self.config.fees.max_bps());
+        Ok(Escrow {
+            asset: self.config.quote_asset.clone(),
+            unit: price.0 as u128 + fee.max(0) as u128,
+        })
+    }
+
+    /// Worst price an order sweeping the book now could reach for its
+    /// quantity, passing over the wallet's own orders that self-trade
+    /// prevention would not trade against; zero when nothing is reachable
+    fn reachable_price(&self, order: &Order) -> Price {
+        let stp = order.self_trade_prevention;
+        let mut worst = Price(0);
+        let mut reached = 0u64;
+        for resting in self.orderbook.orders(order.side.opposite()) {
+            if reached >= order.quantity {
+                break;
+            }
+            if stp != SelfTradePrevention::None && resting.wallet == order.wallet {
+                continue;
+            }
+            worst = resting.price;
+            reached = reached.saturating_add(resting.total_quantity());
+        }
+        worst
+    }
+
+    /// Worst price a market order arriving now could trade at: the deepest
+    /// level it could reach, held to any protection bound. None while the
+    /// opposite side has nothing it could reach.
+    pub fn sweep_price(&self, order: &Order) -> Option<Price> {
+        let reachable = self.reachable_price(order);
+        if reachable.0 == 0 {
+            return None;
+        }
+        Some(match (self.limit_price(order), order.side) {
+            (Some(limit), Side::Buy) => reachable.min(limit),
+            (Some(limit), Side::Sell) => reachable.max(limit),
+            (None, _) => reachable,
+        })
+    }
+
+    /// Wallet that owns a live order, whether resting or waiting to trigger
+    pub fn owner(&self, order_id: Uuid) -> Option<&str> {
+        self.orderbook
+            .get(order_id)
+            .or_else(|| self.stops.get(order_id))
+            .map(|o| o.wallet.as_str())
+    }
+
+    /// Cancel a live order on behalf of `wallet`, or report why there was
+    /// nothing to cancel
+    pub fn cancel(&mut self, order_id: Uuid, wallet: &str) -> CancelResult {
+        if self.owner(order_id).is_some_and(|owner| owner != wallet) {
+            return CancelResult::NotOwner;
+        }
+        self.withdraw(order_id)
+    }
+
+    /// Cancel every live order of `wallet`, optionally on one side only,
+    /// returning the ids cancelled. Untriggered stops are included.
+    pub fn mass_cancel(&mut self, wallet: &str, side: Option<Side>) -> Vec<Uuid> {
+        let wanted = |o: &&Order| side.is_none_or(|side| o.side == side);
+        let order_ids: Vec<Uuid> = self.orderbook
+            .wallet_orders(wallet)
+            .chain(self.stops.buys.values().chain(self.stops.sells.values()).flatten().filter(|o| o.wallet == wallet))
+            .filter(wanted)
+            .map(|o| o.id)
+            .collect();
+
+        for &order_id in &order_ids {
+            self.withdraw(order_id);
+        }
+        order_ids
+    }
+
+    /// Protect `wallet`'s quotes with `limits`, or stop protecting them with
+    /// `None`. Changing limits starts a fresh window and lifts any block.
+    pub fn set_mmp(&mut self, wallet: &str, limits: Option<MmpLimits>) {
+        match limits {
+            Some(limits) => {
+                self.mmp.insert(wallet.to_string(), MmpState::new(limits));
+            }
+            None => {
+                self.mmp.remove(wallet);
+            }
+        }
+    }
+
+    /// Let a wallet whose protection triggered quote again, returning false
+    /// if it has no protection configured
+    pub fn reset_mmp(&mut self, wallet: &str) -> bool {
+        let Some(state) = self.mmp.get_mut(wallet) else {
+            return false;
+        };
+        state.reset();
+        self.events.push(EngineEvent::mmp_reset(self.market.clone(), wallet.to_string()));
+        true
+    }
+
+    pub fn mmp(&self, wallet: &str) -> Option<&MmpState> {
+        self.mmp.get(wallet)
+    }
+
+    /// Rates `wallet` pays here now, by its volume over the fee window
+    pub fn fee_rates(&self, wallet: &str) -> FeeRates {
+        self.config.fees.rates(self.volumes.volume(wallet, self.clock))
+    }
+
+    /// Count a fill of a protected wallet's resting order, pulling all its
+    /// orders once a limit is reached
+    fn record_mmp_fill(&mut self, wallet: &str, side: Side, qty: u64) {
+        let now = self.clock;
+        let Some(state) = self.mmp.get_mut(wallet) else {
+            return;
+        };
+        if state.record(now, side, qty) {
+            let cancelled = self.mass_cancel(wallet, None);
+            self.events.push(EngineEvent::mmp_triggered(self.market.clone(), wallet.to_string(), cancelled));
+        }
+    }
+
+    /// Cancel an order whoever owns it, for the engine's own housekeeping
+    fn withdraw(&mut self, order_id: Uuid) -> CancelResult {
+        if self.remove(order_id).is_none() {
+            return match self.records.get(&order_id).and_then(|record| record.status.closed()) {
+                Some(closed) => CancelResult::AlreadyClosed(closed),
+                None => CancelResult::NotFound,
+            };
+        }
+
+        self.events.push(EngineEvent::order_cancelled(order_id, self.market.clone()));
+        self.close(order_id, Closed::Cancelled);
+        if self.state == TradingState::PreOpen {
+            self.publish_indicative();
+        }
+        CancelResult::Cancelled
+    }
+
+    /// Record how an order left the book
+    fn close(&mut self, order_id: Uuid, closed: Closed) {
+        self.retire(order_id, closed.into());
+    }
+
+    /// Give an order its final status and start its record's retention window
+    fn retire(&mut self, order_id: Uuid, status: OrderStatus) {
+        if let Some(record) = self.records.get_mut(&order_id) {
+            record.status = status;
+            record.closed_at = Some(self.clock);
+            self.closed.entry(self.clock).or_default().push(order_id);
+        }
+    }
+
+    /// Quantity of an order still resting or waiting to trigger, including iceberg reserve
+    pub fn open_quantity(&self, order_id: Uuid) -> u64 {
+        self.orderbook
+            .get(order_id)
+            .or_else(|| self.stops.get(order_id))
+            .map_or(0, Order::total_quantity)
+    }
+
+    /// Status of an order submitted to this market
+    pub fn report(&self, order_id: Uuid) -> Option<OrderReport> {
+        let record = self.records.get(&order_id)?;
+        let open_quantity = self.open_quantity(order_id);
+
+        Some(OrderReport {
+            order_id,
+            market: self.market.clone(),
+            record: record.clone(),
+            open_quantity,
+        })
+    }
+
+    /// Live orders of `wallet`, resting or waiting to trigger, oldest first
+    pub fn open_orders(&self, wallet: &str) -> Vec<OrderReport> {
+        let mut reports: Vec<OrderReport> = self.orderbook
+            .wallet_orders(wallet)
+            .chain(self.stops.buys.values().chain(self.stops.sells.values()).flatten().filter(|o| o.wallet == wallet))
+            .filter_map(|o| self.report(o.id))
+            .collect();
+        reports.sort_by_key(|report| (report.record.timestamp, report.order_id));
+        reports
+    }
+
+    /// Number of live orders of `wallet`, resting or waiting to trigger
+    pub fn open_order_count(&self, wallet: &str) -> usize {
+        let stops = self.stops.buys.values().chain(self.stops.sells.values()).flatten();
+        self.orderbook.wallet_order_count(wallet) + stops.filter(|o| o.wallet == wallet).count()
+    }
 
-            Side::Sell => {
-                while order.quantity > 0 {
-                    // Get best price and check if we can match
-                    let best_price = {
-                        let (price, bids) = match self.orderbook.best_bid() {
-                            Some(v) => v,
-                            None => break,
-                        };
-                        
-                        if order.price > *price {
-                            break;
+    /// Take an order off the visible book or, if it has not triggered yet, the trigger book
+    fn remove(&mut self, order_id: Uuid) -> Option<Order> {
+        self.orderbook
+            .remove(order_id)
+            .or_else(|| self.stops.remove(order_id))
+    }
+    /// Check that `wallet` may replace `order_id`: only a live order of its
+    /// own can be, so a replace never takes over or revives another id
+    pub fn replaceable(&self, order_id: Uuid, wallet: &str) -> Result<(), EngineError> {
+        if let Some(owner) = self.owner(order_id) {
+            return if owner == wallet { Ok(()) } else { Err(EngineError::NotOwner) };
+        }
+        match self.records.get(&order_id) {
+            Some(record) if record.wallet != wallet => Err(EngineError::NotOwner),
+            Some(record) => Err(record.status.closed().map_or(EngineError::OrderNotFound, EngineError::OrderClosed)),
+            None => Err(EngineError::OrderNotFound),
+        }
+    }
+
+    /// Cancel a live order and submit `order` in its place under the same id
+    pub fn replace(&mut self, order: Order) -> Result<SubmitOutcome, EngineError> {
+        self.replaceable(order.id, &order.wallet)?;
+
+        // The replacement reuses the id, so it starts with a clean record
+        self.withdraw(order.id);
+        self.records.remove(&order.id);
+        Ok(self.submit(order))
+    }
+
+    /// Change a live order's price or size. A size decrease at the same
+    /// price keeps the order's place in the queue; anything else takes it off
+    /// the book and matches it again as if it had just arrived. Untriggered
+    /// stops stay parked at their stop price.
+    pub fn amend(&mut self, amendment: Amendment) -> AmendOutcome {
+        self.tick(amendment.timestamp);
+        let order_id = amendment.order_id;
+
+        let parked = self.stops.get(order_id);
+        let Some(current) = self.orderbook.get(order_id).or(parked) else {
+            return AmendOutcome::rejected(order_id, RejectReason::UnknownOrder);
+        };
+        if current.wallet != amendment.wallet {
+            return AmendOutcome::rejected(order_id, RejectReason::WalletMismatch);
+        }
+        if current.side != amendment.side {
+            return AmendOutcome::rejected(order_id, RejectReason::SideMismatch);
+        }
+
+        let amended = amendment.apply(current);
+        if parked.is_some() {
+            let priority_kept = amendment.keeps_priority(current);
+            return self.amend_stop(amended, priority_kept);
+        }
+        if amendment.keeps_priority(current) {
+            let reduction = current.total_quantity() - amended.quantity;
+            self.amend_record(&amended);
+            if self.orderbook.reduce(order_id, reduction) == Some(0) {
+                self.close(order_id, Closed::Cancelled);
+            }
+            self.events.push(EngineEvent::order_amended(order_id, self.market.clone(), amended.price, true));
+
+            let mut outcome = SubmitOutcome::new(order_id);
+            outcome.rested = amended.quantity;
+            return AmendOutcome { priority_kept: true, outcome };
+        }
+
+        // Refuse up front rather than lose the original order
+        if let Some(reason) = self.refusal(&amended) {
+            return AmendOutcome::rejected(order_id, reason);
+        }
+
+        self.orderbook.remove(order_id);
+        self.amend_record(&amended);
+        self.events.push(EngineEvent::order_amended(order_id, self.market.clone(), amended.price, false));
+
+        let mut outcome = self.activate(amended);
+        if outcome.rejected.is_some() {
+            self.events.push(EngineEvent::order_cancelled(order_id, self.market.clone()));
+            self.close(order_id, Closed::Cancelled);
+        }
+        outcome.triggered = self.run_triggers(&mut outcome.trades);
+        AmendOutcome { priority_kept: false, outcome }
+    }
+
+    /// Change an untriggered stop in place, or move it behind the other stops
+    /// at its stop price unless only its size went down
+    fn amend_stop(&mut self, amended: Order, priority_kept: bool) -> AmendOutcome {
+        let order_id = amended.id;
+        self.amend_record(&amended);
+        self.events.push(EngineEvent::order_amended(order_id, self.market.clone(), amended.price, priority_kept));
+
+        let mut outcome = SubmitOutcome::new(order_id);
+        outcome.parked = amended.total_quantity();
+        if amended.total_quantity() == 0 {
+            self.stops.remove(order_id);
+            self.close(order_id, Closed::Cancelled);
+        } else if priority_kept {
+            self.stops.update(amended);
+        } else {
+            self.stops.remove(order_id);
+            self.stops.add(amended);
+        }
+        AmendOutcome { priority_kept, outcome }
+    }
+
+    /// Bring an amended order's record up to date; fills so far still count
+    /// towards its quantity
+    fn amend_record(&mut self, amended: &Order) {
+        if let Some(record) = self.records.get_mut(&amended.id) {
+            record.price = amended.price;
+            record.quantity = record.filled + amended.total_quantity();
+        }
+    }
+
+    pub fn submit(&mut self, order: Order) -> SubmitOutcome {
+        self.tick(order.timestamp);
+        self.records.insert(order.id, OrderRecord::new(&order));
+
+        let order_id = order.id;
+        let outcome = self.accept(order);
+        if outcome.rejected.is_some() {
+            self.retire(order_id, OrderStatus::Rejected);
+        }
+        outcome
+    }
+
+    /// Park, match or rest an order that is already on record
+    fn accept(&mut self, mut order: Order) -> SubmitOutcome {
+        // Orders that cannot rest are still allowed so the wallet can hedge
+        let quote = order.time_in_force.rests() && !order.order_type.is_market();
+        if quote && self.mmp.get(&order.wallet).is_some_and(|state| state.triggered) {
+            return SubmitOutcome::rejected(order.id, RejectReason::MmpTriggered);
+        }
+
+        if let Some(expires_at) = order.time_in_force.expires_at() {
+            if expires_at <= self.clock {
+                return SubmitOutcome::rejected(order.id, RejectReason::Expired);
+            }
+            self.expiries.entry(expires_at).or_default().push(order.id);
+        }
+
+        let mut outcome = match order.order_type.stop_price() {
+            Some(stop) if !self.stop_reached(order.side, stop) => self.park(order),
+            Some(_) => {
+                order.trigger();
+                self.activate(order)
+            }
+            None => self.activate(order),
+        };
+
+        outcome.triggered = self.run_triggers(&mut outcome.trades);
+        outcome
+    }
+
+    /// Match the order, or during the call period or a batch just collect it
+    fn activate(&mut self, order: Order) -> SubmitOutcome {
+        if self.collecting() {
+            self.collect(order)
+        } else {
+            self.execute(order)
+        }
+    }
+
+    fn collecting(&self) -> bool {
+        self.state == TradingState::PreOpen || self.batching()
+    }
+
+    /// Why `activate` would refuse a resting order outright, if it would
+    fn refusal(&self, order: &Order) -> Option<RejectReason> {
+        if self.collecting() {
+            return (!waits_for_uncross(order)).then_some(RejectReason::NotAllowedInAuction);
+        }
+        let would_cross = order.time_in_force == TimeInForce::PostOnly
+            && self.orderbook
+                .best_price(order.side.opposite())
+                .is_some_and(|best| crosses(order.side, Some(order.price), best));
+        would_cross.then_some(RejectReason::PostOnlyWouldCross)
+    }
+
+    /// Rest the order without matching until the next uncross. Only orders
+    /// that can wait for it are accepted. The call period publishes the new
+    /// indicative price; batches stay sealed so nobody can trade ahead of them.
+    fn collect(&mut self, order: Order) -> SubmitOutcome {
+        if !waits_for_uncross(&order) {
+            return SubmitOutcome::rejected(order.id, RejectReason::NotAllowedInAuction);
+        }
+
+        let mut outcome = SubmitOutcome::new(order.id);
+        outcome.rested = order.quantity;
+        if self.state == TradingState::PreOpen {
+            self.rest(order);
+            self.publish_indicative();
+        } else {
+            self.batch.push(order.id);
+            self.rest(order);
+        }
+        outcome
+    }
+
+    /// Whether the last trade price has reached a stop on `side`
+    fn stop_reached(&self, side: Side, stop: Price) -> bool {
+        match (side, self.last_trade_price) {
+            (_, None) => false,
+            (Side::Buy, Some(last)) => last >= stop,
+            (Side::Sell, Some(last)) => last <= stop,
+        }
+    }
+
+    fn park(&mut self, order: Order) -> SubmitOutcome {
+        let mut outcome = SubmitOutcome::new(order.id);
+        outcome.parked = order.quantity;
+        self.stops.add(order);
+        outcome
+    }
+
+    /// Fire stops reached by the last trade price until none are left,
+    /// appending their fills to `trades` and returning the ids fired; a
+    /// triggered order's own fills move the price and may fire further stops.
+    /// A triggered order the book refuses is reported as cancelled.
+    fn run_triggers(&mut self, trades: &mut Vec<Trade>) -> Vec<Uuid> {
+        let mut triggered = vec![];
+        while let Some(last) = self.last_trade_price {
+            let Some(mut order) = self.stops.pop_triggered(last) else {
+                break;
+            };
+
+            self.events.push(EngineEvent::stop_triggered(order.id, self.market.clone(), last));
+            triggered.push(order.id);
+
+            order.trigger();
+            let order_id = order.id;
+            let outcome = self.activate(order);
+            if outcome.rejected.is_some() {
+                self.events.push(EngineEvent::order_cancelled(order_id, self.market.clone()));
+                self.close(order_id, Closed::Cancelled);
+            }
+            trades.extend(outcome.trades);
+        }
+        triggered
+    }
+
+    /// Match an active (non-stop) order and rest or drop the remainder
+    fn execute(&mut self, mut order: Order) -> SubmitOutcome {
+        let mut outcome = SubmitOutcome::new(order.id);
+        let opposite = order.side.opposite();
+        let mut limit = self.limit_price(&order);
+        // A market buy holds funds for the deepest level it can reach on
+        // arrival, so it goes no deeper if protection pulls quotes mid-sweep
+        if order.side == Side::Buy && order.order_type.is_market() {
+            let reachable = self.reachable_price(&order);
+            limit = Some(limit.map_or(reachable, |limit| limit.min(reachable)));
+        }
+
+        if order.time_in_force.is_post_only() {
+            if let Some(best) = self.orderbook.best_price(opposite) {
+                if crosses(order.side, limit, best) {
+                    match slide_behind(order.side, best, self.config.tick_size) {
+                        Some(price) if order.time_in_force == TimeInForce::PostOnlySlide => {
+                            order.price = price;
                         }
-                        *price
-                    };
-
-                    // Now we can safely borrow mutably again
-                    let (_, bids) = self.orderbook.best_bid().unwrap();
-                    let mut resting = bids.pop_front().unwrap();
-                    let qty = order.quantity.min(resting.quantity);
-
-                    order.quantity -= qty;
-                    resting.quantity -= qty;
-
-                    // Create trade (borrow ended, so we can access self)
-                    self.sequence += 1;
-                    let trade = Trade {
-                        market: self.market.clone(),
-                        buy_order: resting.id,
-                        sell_order: order.id,
-                        price: best_price,
-                        quantity: qty,
-                        sequence: self.sequence,
-                    };
-                    trades.push(trade);
-
-                    if resting.quantity > 0 {
-                        bids.push_front(resting);
+                        _ => return SubmitOutcome::rejected(order.id, RejectReason::PostOnlyWouldCross),
                     }
                 }
             }
         }
 
-        self.orderbook.cleanup();
+        // Fill-or-kill must know it can complete before it touches the book
+        if order.time_in_force == TimeInForce::Fok {
+            let available = self.available_liquidity(&order, limit);
+            if available < order.quantity {
+                return SubmitOutcome::rejected(order.id, RejectReason::FillOrKillUnfillable);
+            }
+        }
+
+        if !order.time_in_force.is_post_only() {
+            self.match_order(&mut order, limit, &mut outcome);
+        }
 
-        if order.quantity > 0 {
-            self.orderbook.add(order);
+        // Market orders never rest; whatever could not be filled is dropped
+        if order.quantity > 0 && order.time_in_force.rests() && !order.order_type.is_market() {
+            outcome.rested = order.quantity;
+            self.rest(order);
+        } else {
+            outcome.cancelled += order.quantity;
+            let closed = if outcome.cancelled == 0 { Closed::Filled } else { Closed::Cancelled };
+            self.close(order.id, closed);
         }
 
-        trades
+        outcome
+    }
+
+    fn rest(&mut self, order: Order) {
+        self.events.push(EngineEvent::order_added(order.id, self.market.clone()));
+        self.orderbook.add(order);
+    }
+
+    /// Worst price the order may trade at; None means it may trade at any price
+    fn limit_price(&self, order: &Order) -> Option<Price> {
+        match order.order_type {
+            OrderType::Limit | OrderType::StopLimit { .. } => Some(order.price),
+            OrderType::Market | OrderType::Stop { .. } => None,
+            OrderType::ProtectedMarket(protection) => self.orderbook
+                .best_price(order.side.opposite())
+                .map(|reference| protection.bound(order.side, reference)),
+        }
+    }
+
+    /// Quantity `order` could trade against at or within `limit`, counted only
+    /// until its full quantity is reached
+    fn available_liquidity(&self, order: &Order, limit: Option<Price>) -> u64 {
+        let stp = order.self_trade_prevention;
+        let mut available = 0u64;
+
+        for resting in self.orderbook.orders(order.side.opposite()) {
+            if !crosses(order.side, limit, resting.price) || available >= order.quantity {
+                break;
+            }
+            if stp != SelfTradePrevention::None && resting.wallet == order.wallet {
+                // Own orders are cancelled rather than traded; any other mode
+                // stops or shrinks the incoming order from here on
+                if stp == SelfTradePrevention::CancelOldest {
+                    continue;
+                }
+                break;
+            }
+            available = available.saturating_add(resting.total_quantity());
+        }
+        available
+    }
+
+    /// Walk the opposite side of the book while it crosses `limit`, recording
+    /// trades and self-trade cancellations in `outcome` and reducing
+    /// `order.quantity` by both
+    fn match_order(&mut self, order: &mut Order, limit: Option<Price>, outcome: &mut SubmitOutcome) {
+        let opposite = order.side.opposite();
+
+        while order.quantity > 0 {
+            let Some(resting) = self.orderbook.best_order(opposite) else {
+                break;
+            };
+            let price = resting.price;
+
+            if !crosses(order.side, limit, price) {
+                break;
+            }
+
+            let mode = order.self_trade_prevention;
+            if mode != SelfTradePrevention::None && resting.wallet == order.wallet {
+                let (taker_cancelled, maker_cancelled) =
+                    mode.cancellations(order.quantity, resting.total_quantity());
+                let maker_id = resting.id;
+
+                order.quantity -= taker_cancelled;
+                outcome.cancelled += taker_cancelled;
+                if self.orderbook.reduce(maker_id, maker_cancelled) == Some(0) {
+                    self.close(maker_id, Closed::Cancelled);
+                }
+
+                self.events.push(EngineEvent::self_trade_prevented(
+                    self.market.clone(),
+                    mode,
+                    order.id,
+                    maker_id,
+                    taker_cancelled,
+                    maker_cancelled,
+                ));
+                continue;
+            }
+
+            let qty = order.quantity.min(resting.quantity);
+            let maker_wallet = resting.wallet.clone();
+            order.quantity -= qty;
+            let resting_id = self.fill_best(opposite, qty);
+
+            let maker = Party { order: resting_id, wallet: maker_wallet };
+            let taker = Party { order: order.id, wallet: order.wallet.clone() };
+            let trade = self.record_trade(maker, taker, Some(order.side), price, qty, None);
+            outcome.filled += qty;
+
+            self.record_mmp_fill(&trade.maker_wallet, opposite, qty);
+            outcome.trades.push(trade);
+        }
+    }
+
+    /// Fill `qty` from the order at the front of the best level on `side`,
+    /// refreshing icebergs; the book drops the level once it is empty
+    fn fill_best(&mut self, side: Side, qty: u64) -> Uuid {
+        let resting = self.orderbook.best_order(side).expect("caller checked the level");
+        let (resting_id, price) = (resting.id, resting.price);
+
+        match self.orderbook.fill(resting_id, qty) {
+            Some(Fill::Replenished { displayed }) => {
+                // A fresh slice goes to the back of the level like a new order
+                self.events.push(EngineEvent::iceberg_refreshed(resting_id, self.market.clone(), price, displayed));
+            }
+            Some(Fill::Complete) => {
+                self.close(resting_id, Closed::Filled);
+            }
+            Some(Fill::Partial) | None => {}
+        }
+        resting_id
+    }
+
+    /// The order and owner on one side of an auction trade, read before the
+    /// fill can take the order off the book
+    fn party(&self, order_id: Uuid) -> Party {
+        let wallet = self.orderbook.get(order_id).map(|order| order.wallet.clone()).unwrap_or_default();
+        Party { order: order_id, wallet }
+    }
+
+    /// Record a trade between `maker` and `taker`; with no `taker_side`, as in
+    /// an auction, the maker is taken to be the buyer
+    fn record_trade(&mut self, maker: Party, taker: Party, taker_side: Option<Side>, price: Price, quantity: u64, auction_sequence: Option<u64>) -> Trade {
+        self.sequence += 1;
+        self.last_trade_price = Some(price);
+        for order_id in [maker.order, taker.order] {
+            if let Some(record) = self.records.get_mut(&order_id) {
+                record.fill(price, quantity);
+            }
+        }
+
+        let (buy_order, sell_order) = match taker_side {
+            Some(Side::Buy) => (taker.order, maker.order),
+            Some(Side::Sell) | None => (maker.order, taker.order),
+        };
+
+        // Tiers go by volume before this trade, so both sides are priced first
+        let notional = price.0 as u128 * quantity as u128;
+        let maker_rates = self.fee_rates(&maker.wallet);
+        let taker_rates = self.fee_rates(&taker.wallet);
+        let maker_bps = if taker_side.is_some() { maker_rates.maker_bps } else { maker_rates.taker_bps };
+        for party in [&maker, &taker] {
+            self.volumes.record(&party.wallet, self.clock, notional);
+        }
+        let trade = Trade {
+            market: self.market.clone(),
+            buy_order,
+            sell_order,
+            price,
+            quantity,
+            sequence: self.sequence,
+            auction_sequence,
+            taker_side,
+            maker_order: maker.order,
+            taker_order: taker.order,
+            maker_wallet: maker.wallet,
+            taker_wallet: taker.wallet,
+            executed_at: self.clock,
+            maker_fee: fees::fee(notional, maker_bps),
+            taker_fee: fees::fee(notional, taker_rates.taker_bps),
+            fee_currency: self.config.quote_asset.clone(),
+        };
+        self.events.push(EngineEvent::trade_executed(trade.clone()));
+        trade
+    }
+
+}
+
+/// One side of a trade
+struct Party {
+    order: Uuid,
+    wallet: String,
+}
+
+/// Whether an order can rest until the next uncross: market, immediate and
+/// post-only orders cannot
+fn waits_for_uncross(order: &Order) -> bool {
+    let tif = order.time_in_force;
+    !order.order_type.is_market() && tif.rests() && !tif.is_post_only()
+}
+
+/// Whether an order on `side` with the given limit can trade against `price`
+fn crosses(side: Side, limit: Option<Price>, price: Price) -> bool {
+    match (side, limit) {
+        (_, None) => true,
+        (Side::Buy, Some(limit)) => price <= limit,
+        (Side::Sell, Some(limit)) => price >= limit,
     }
+}
 
-}
\ No newline at end of file
+/// Most aggressive price on `side` that does not cross `best_opposite`
+fn slide_behind(side: Side, best_opposite: Price, tick_size: u64) -> Option<Price> {
+    match side {
+        Side::Buy => best_opposite.0.checked_sub(tick_size).filter(|p| *p > 0).map(Price),
+        Side::Sell => best_opposite.0.checked_add(tick_size).map(Price),
+    }
+}
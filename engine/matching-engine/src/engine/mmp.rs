@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+use crate::engine::error::EngineError;
+use crate::models::side::Side;
+use serde::{Serialize, Deserialize};
+
+/// Market maker protection limits for one wallet in one market
+///
+/// Fills of the wallet's resting orders, auction and batch fills included,
+/// are counted over a rolling window of engine time. Reaching any limit
+/// pulls all of the wallet's orders in the market and blocks new quotes
+/// until the wallet resets. A zero limit is not checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MmpLimits {
+    pub window_ms: u64,
+    /// Quantity filled, buys and sells together
+    pub quantity: u64,
+    /// Quantity bought minus quantity sold, in either direction
+    pub delta: u64,
+    /// Number of fills
+    pub fills: u64,
+}
+
+impl MmpLimits {
+    pub fn check(&self) -> Result<(), EngineError> {
+        if self.window_ms == 0 {
+            return Err(EngineError::InvalidMmpWindow);
+        }
+        if self.quantity == 0 && self.delta == 0 && self.fills == 0 {
+            return Err(EngineError::MissingMmpLimits);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct MmpFill {
+    at: u64,
+    side: Side,
+    quantity: u64,
+}
+
+/// A wallet's protection limits and the fills counted against them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MmpState {
+    pub limits: MmpLimits,
+    /// Set once a limit is reached; new quotes are refused until reset
+    pub triggered: bool,
+    fills: VecDeque<MmpFill>,
+}
+
+impl MmpState {
+    pub fn new(limits: MmpLimits) -> Self {
+        Self { limits, triggered: false, fills: VecDeque::new() }
+    }
+
+    /// Count a fill of a resting order on `side` at engine time `now`,
+    /// returning true if it reached a limit. Fills after triggering are not
+    /// counted, so the window starts afresh on reset.
+    pub fn record(&mut self, now: u64, side: Side, quantity: u64) -> bool {
+        if self.triggered {
+            return false;
+        }
+
+        let window = self.limits.window_ms;
+        while self.fills.front().is_some_and(|fill| fill.at.saturating_add(window) <= now) {
+            self.fills.pop_front();
+        }
+        self.fills.push_back(MmpFill { at: now, side, quantity });
+
+        let (mut filled, mut delta) = (0u64, 0i128);
+        for fill in &self.fills {
+            filled = filled.saturating_add(fill.quantity);
+            delta += match fill.side {
+                Side::Buy => fill.quantity as i128,
+                Side::Sell => -(fill.quantity as i128),
+            };
+        }
+
+        let limits = self.limits;
+        let reached = |value: u128, limit: u64| limit > 0 && value >= limit as u128;
+        self.triggered = reached(filled as u128, limits.quantity)
+            || reached(delta.unsigned_abs(), limits.delta)
+            || reached(self.fills.len() as u128, limits.fills);
+        if self.triggered {
+            self.fills.clear();
+        }
+        self.triggered
+    }
+
+    pub fn reset(&mut self) {
+        self.triggered = false;
+        self.fills.clear();
+    }
+}
@@ -2,4 +2,15 @@ pub mod orderbook;
 pub mod matching;
 pub mod market;
 pub mod risk;
-pub mod events;
\ No newline at end of file
+pub mod config;
+pub mod state;
+pub mod events;
+pub mod outcome;
+pub mod stops;
+pub mod auction;
+pub mod mmp;
+pub mod lifecycle;
+pub mod client_ids;
+pub mod error;
+pub mod fees;
+pub mod ledger;
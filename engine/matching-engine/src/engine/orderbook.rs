@@ -1,49 +1,331 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use crate::models::{order::Order, side::Side, price::Price};
-use std::collections::HashMap;
 use uuid::Uuid;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Serializer, Deserialize};
 
-#[derive(Serialize, Deserialize)]
+/// Position of an order in the arena
+type Slot = usize;
+
+/// A resting order and its neighbours in its level's queue
+struct Node {
+    order: Order,
+    prev: Option<Slot>,
+    next: Option<Slot>,
+}
+
+/// A price level: a FIFO queue threaded through the arena
+struct Level {
+    head: Slot,
+    tail: Slot,
+}
+
+/// What filling a resting order's displayed quantity did to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fill {
+    /// Some displayed quantity is left
+    Partial,
+    /// The iceberg slice ran out and was refilled from reserve; the order
+    /// moved to the back of its level showing `displayed`
+    Replenished { displayed: u64 },
+    /// Nothing is left and the order has left the book
+    Complete,
+}
+
+/// Resting orders for one market
+///
+/// Orders live in an arena whose vacated slots are recycled, and every price
+/// level is a doubly-linked queue through it. Once `index` has located an
+/// order it can be unlinked, amended or filled in O(1) however deep its level
+/// is, and a level is dropped as soon as its last order leaves. A second
+/// index by wallet finds everything one participant has resting.
+#[derive(Default, Deserialize)]
+#[serde(from = "Snapshot")]
 pub struct OrderBook {
-    pub bids: BTreeMap<Price, VecDeque<Order>>,
-    pub asks: BTreeMap<Price, VecDeque<Order>>,
-    pub index: HashMap<Uuid, (Price, Side)>,
+    nodes: Vec<Option<Node>>,
+    free: Vec<Slot>,
+    bids: BTreeMap<Price, Level>,
+    asks: BTreeMap<Price, Level>,
+    index: HashMap<Uuid, Slot>,
+    by_wallet: HashMap<String, BTreeSet<Uuid>>,
 }
 
 impl OrderBook {
     pub fn new() -> Self {
-        Self {
-            bids: BTreeMap::new(),
-            asks: BTreeMap::new(),
-            index: HashMap::new(),
+        Self::default()
+    }
+
+    /// Rest an order at the back of its level; icebergs only show their display slice
+    pub fn add(&mut self, mut order: Order) {
+        order.conceal();
+        self.insert(order);
+    }
+
+    fn insert(&mut self, order: Order) {
+        let (id, side, price) = (order.id, order.side, order.price);
+        self.by_wallet.entry(order.wallet.clone()).or_default().insert(id);
+        let node = Node { order, prev: None, next: None };
+
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = Some(node);
+                slot
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        self.index.insert(id, slot);
+        self.push_back(side, price, slot);
+    }
+
+    /// Take a resting order off the book, dropping its level if it was the last one
+    pub fn remove(&mut self, order_id: Uuid) -> Option<Order> {
+        let slot = self.index.remove(&order_id)?;
+        self.unlink(slot);
+        self.free.push(slot);
+        let order = self.nodes[slot].take()?.order;
+
+        if let Some(orders) = self.by_wallet.get_mut(&order.wallet) {
+            orders.remove(&order_id);
+            if orders.is_empty() {
+                self.by_wallet.remove(&order.wallet);
+            }
+        }
+        Some(order)
+    }
+
+    pub fn get(&self, order_id: Uuid) -> Option<&Order> {
+        self.index.get(&order_id).map(|&slot| &self.node(slot).order)
+    }
+
+    pub fn contains(&self, order_id: Uuid) -> bool {
+        self.index.contains_key(&order_id)
+    }
+
+    /// Resting orders belonging to `wallet`, on both sides
+    pub fn wallet_orders(&self, wallet: &str) -> impl Iterator<Item = &Order> + '_ {
+        self.by_wallet
+            .get(wallet)
+            .into_iter()
+            .flatten()
+            .map(|order_id| &self.node(self.index[order_id]).order)
+    }
+
+    /// Number of resting orders belonging to `wallet`, without visiting them
+    pub fn wallet_order_count(&self, wallet: &str) -> usize {
+        self.by_wallet.get(wallet).map_or(0, BTreeSet::len)
+    }
+
+    /// Number of resting orders on both sides
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Order at the front of the best level on `side`
+    pub fn best_order(&self, side: Side) -> Option<&Order> {
+        let level = match side {
+            Side::Buy => self.bids.values().next_back(),
+            Side::Sell => self.asks.values().next(),
+        };
+        level.map(|level| &self.node(level.head).order)
+    }
+
+    pub fn best_price(&self, side: Side) -> Option<Price> {
+        match side {
+            Side::Buy => self.bids.keys().next_back().copied(),
+            Side::Sell => self.asks.keys().next().copied(),
+        }
+    }
+
+    /// Trade `qty` off an order's displayed quantity. An exhausted iceberg
+    /// slice is refilled from reserve and requeued like a new order; any other
+    /// exhausted order leaves the book.
+    pub fn fill(&mut self, order_id: Uuid, qty: u64) -> Option<Fill> {
+        let slot = *self.index.get(&order_id)?;
+        let order = &mut self.node_mut(slot).order;
+        order.quantity -= qty;
+
+        if order.quantity > 0 {
+            return Some(Fill::Partial);
+        }
+        if order.replenish() {
+            let (side, price, displayed) = (order.side, order.price, order.quantity);
+            self.unlink(slot);
+            self.push_back(side, price, slot);
+            return Some(Fill::Replenished { displayed });
+        }
+        self.remove(order_id);
+        Some(Fill::Complete)
+    }
+
+    /// Take `qty` off an order, hidden reserve first so the displayed slice
+    /// keeps its place, and return what is left in total. The order leaves
+    /// the book once nothing is left.
+    pub fn reduce(&mut self, order_id: Uuid, qty: u64) -> Option<u64> {
+        let slot = *self.index.get(&order_id)?;
+        let order = &mut self.node_mut(slot).order;
+        order.reduce(qty);
+
+        let left = order.total_quantity();
+        if left == 0 {
+            self.remove(order_id);
+        }
+        Some(left)
+    }
+
+    /// Exchange the queue positions of two orders resting at the same price
+    pub fn swap(&mut self, a: Uuid, b: Uuid) {
+        let (Some(&slot_a), Some(&slot_b)) = (self.index.get(&a), self.index.get(&b)) else {
+            return;
+        };
+        if slot_a == slot_b {
+            return;
+        }
+        debug_assert!(self.node(slot_a).order.price == self.node(slot_b).order.price);
+
+        let mut node_a = self.nodes[slot_a].take().expect("indexed slots are occupied");
+        std::mem::swap(&mut node_a.order, &mut self.node_mut(slot_b).order);
+        self.nodes[slot_a] = Some(node_a);
+        self.index.insert(a, slot_b);
+        self.index.insert(b, slot_a);
+    }
+
+    /// Levels on the given side from best to worst, with the displayed quantity
+    /// at each. Iceberg reserves are never included, so this is safe to publish.
+    pub fn levels(&self, side: Side) -> Box<dyn Iterator<Item = (Price, u64)> + '_> {
+        Box::new(self.side_levels(side).map(|(price, level)| {
+            (price, self.queue(level).map(|o| o.quantity).sum())
+        }))
+    }
+
+    /// Like `levels` but counting iceberg reserves too; for the engine's own
+    /// use only, never for publishing
+    pub fn liquidity(&self, side: Side) -> Box<dyn Iterator<Item = (Price, u64)> + '_> {
+        Box::new(self.side_levels(side).map(|(price, level)| {
+            (price, self.queue(level).map(Order::total_quantity).fold(0, u64::saturating_add))
+        }))
+    }
+
+    /// Resting orders on the given side in matching order: best price first,
+    /// then time priority within each level
+    pub fn orders(&self, side: Side) -> Box<dyn Iterator<Item = &Order> + '_> {
+        Box::new(self.side_levels(side).flat_map(|(_, level)| self.queue(level)))
+    }
+
+    fn side_levels(&self, side: Side) -> Box<dyn Iterator<Item = (Price, &Level)> + '_> {
+        match side {
+            Side::Buy => Box::new(self.bids.iter().rev().map(|(price, level)| (*price, level))),
+            Side::Sell => Box::new(self.asks.iter().map(|(price, level)| (*price, level))),
         }
     }
 
-    pub fn add(&mut self, order: Order) {
-        self.index.insert(order.id, (order.price, order.side));
-        
-        let book = match order.side {
+    fn queue<'a>(&'a self, level: &Level) -> impl Iterator<Item = &'a Order> + 'a {
+        std::iter::successors(Some(level.head), |&slot| self.node(slot).next)
+            .map(|slot| &self.node(slot).order)
+    }
+
+    fn book_mut(&mut self, side: Side) -> &mut BTreeMap<Price, Level> {
+        match side {
             Side::Buy => &mut self.bids,
             Side::Sell => &mut self.asks,
-        };
+        }
+    }
 
-        book.entry(order.price)
-            .or_insert_with(VecDeque::new)
-            .push_back(order);
+    fn node(&self, slot: Slot) -> &Node {
+        self.nodes[slot].as_ref().expect("linked slots are occupied")
     }
 
-    pub fn best_bid(&mut self) -> Option<(&Price, &mut VecDeque<Order>)> {
-        self.bids.iter_mut().next_back()
+    fn node_mut(&mut self, slot: Slot) -> &mut Node {
+        self.nodes[slot].as_mut().expect("linked slots are occupied")
+    }
+
+    fn push_back(&mut self, side: Side, price: Price, slot: Slot) {
+        let tail = self.book_mut(side).get(&price).map(|level| level.tail);
+        let node = self.node_mut(slot);
+        node.prev = tail;
+        node.next = None;
+
+        match tail {
+            Some(tail) => {
+                self.node_mut(tail).next = Some(slot);
+                if let Some(level) = self.book_mut(side).get_mut(&price) {
+                    level.tail = slot;
+                }
+            }
+            None => {
+                self.book_mut(side).insert(price, Level { head: slot, tail: slot });
+            }
+        }
     }
 
-    pub fn best_ask(&mut self) -> Option<(&Price, &mut VecDeque<Order>)> {
-        self.asks.iter_mut().next()
+    /// Detach a slot from its level's queue, dropping the level if it empties
+    fn unlink(&mut self, slot: Slot) {
+        let node = self.node(slot);
+        let (prev, next) = (node.prev, node.next);
+        let (side, price) = (node.order.side, node.order.price);
+
+        if let Some(prev) = prev {
+            self.node_mut(prev).next = next;
+        }
+        if let Some(next) = next {
+            self.node_mut(next).prev = prev;
+        }
+
+        let book = self.book_mut(side);
+        match (prev, next) {
+            (None, None) => {
+                book.remove(&price);
+            }
+            (prev, next) => {
+                let level = book.get_mut(&price).expect("linked orders have a level");
+                if let (None, Some(next)) = (prev, next) {
+                    level.head = next;
+                }
+                if let (Some(prev), None) = (prev, next) {
+                    level.tail = prev;
+                }
+            }
+        }
     }
+}
+
+/// Snapshot form of the book: resting orders per level in queue order
+#[derive(Deserialize)]
+struct Snapshot {
+    #[serde(default)]
+    bids: BTreeMap<Price, Vec<Order>>,
+    #[serde(default)]
+    asks: BTreeMap<Price, Vec<Order>>,
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    bids: BTreeMap<Price, Vec<&'a Order>>,
+    asks: BTreeMap<Price, Vec<&'a Order>>,
+}
 
-    pub fn cleanup(&mut self) {
-        self.bids.retain(|_, q| !q.is_empty());
-        self.asks.retain(|_, q| !q.is_empty());
+impl Serialize for OrderBook {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let levels = |side| {
+            self.side_levels(side)
+                .map(|(price, level)| (price, self.queue(level).collect()))
+                .collect()
+        };
+        SnapshotRef { bids: levels(Side::Buy), asks: levels(Side::Sell) }.serialize(serializer)
     }
+}
 
+impl From<Snapshot> for OrderBook {
+    fn from(snapshot: Snapshot) -> Self {
+        let mut book = OrderBook::new();
+        for order in snapshot.bids.into_values().chain(snapshot.asks.into_values()).flatten() {
+            book.insert(order);
+        }
+        book
+    }
 }
@@ -0,0 +1,138 @@
+use crate::engine::error::EngineError;
+use crate::models::trade::Trade;
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+/// Why an otherwise valid order was refused by the matching engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    /// A post-only order would have taken liquidity
+    PostOnlyWouldCross,
+    /// A fill-or-kill order could not be filled in full
+    FillOrKillUnfillable,
+    /// A good-till-date order arrived after its expiry time
+    Expired,
+    /// Market, immediate-or-cancel, fill-or-kill and post-only orders cannot
+    /// wait for an auction uncross
+    NotAllowedInAuction,
+    /// An amend named an order that is neither resting nor waiting to trigger
+    UnknownOrder,
+    /// An amend came from a wallet that does not own the order
+    WalletMismatch,
+    /// An amend named the other side of the book from the order
+    SideMismatch,
+    /// The wallet's market maker protection triggered and it has not reset
+    MmpTriggered,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::PostOnlyWouldCross => "POST_ONLY_WOULD_CROSS",
+            RejectReason::FillOrKillUnfillable => "FOK_UNFILLABLE",
+            RejectReason::Expired => "EXPIRED",
+            RejectReason::NotAllowedInAuction => "NOT_ALLOWED_IN_AUCTION",
+            RejectReason::UnknownOrder => "UNKNOWN_ORDER",
+            RejectReason::WalletMismatch => "WALLET_MISMATCH",
+            RejectReason::SideMismatch => "SIDE_MISMATCH",
+            RejectReason::MmpTriggered => "MMP_TRIGGERED",
+        }
+    }
+}
+
+/// How an order that is no longer live left the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Closed {
+    Filled,
+    Cancelled,
+    Expired,
+}
+
+/// What a cancel request found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CancelResult {
+    /// The order was live and has been taken off the book
+    Cancelled,
+    /// The order had already left the book
+    AlreadyClosed(Closed),
+    /// The order belongs to another wallet and was left alone
+    NotOwner,
+    /// The engine has no record of the order
+    NotFound,
+}
+
+impl CancelResult {
+    /// Why the cancel had nothing to do, if it did not cancel anything
+    pub fn error(self) -> Option<EngineError> {
+        match self {
+            CancelResult::Cancelled => None,
+            CancelResult::AlreadyClosed(closed) => Some(EngineError::OrderClosed(closed)),
+            CancelResult::NotOwner => Some(EngineError::NotOwner),
+            CancelResult::NotFound => Some(EngineError::OrderNotFound),
+        }
+    }
+}
+
+/// What happened to a submitted order
+///
+/// `filled + rested + cancelled + parked` always equals the submitted
+/// quantity, except for rejected orders where all four are zero.
+/// `trades` holds every trade the submission produced, including those of
+/// stop orders it triggered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitOutcome {
+    pub order_id: Uuid,
+    pub trades: Vec<Trade>,
+    /// Quantity of the submitted order that traded
+    pub filled: u64,
+    /// Quantity left resting on the book
+    pub rested: u64,
+    /// Quantity dropped without trading (IOC and market remainders)
+    pub cancelled: u64,
+    /// Quantity held in the trigger book until its stop price is reached
+    pub parked: u64,
+    /// Stop orders activated as a result of this submission, in trigger order
+    pub triggered: Vec<Uuid>,
+    pub rejected: Option<RejectReason>,
+}
+
+impl SubmitOutcome {
+    pub fn new(order_id: Uuid) -> Self {
+        Self {
+            order_id,
+            trades: vec![],
+            filled: 0,
+            rested: 0,
+            cancelled: 0,
+            parked: 0,
+            triggered: vec![],
+            rejected: None,
+        }
+    }
+
+    pub fn rejected(order_id: Uuid, reason: RejectReason) -> Self {
+        Self {
+            rejected: Some(reason),
+            ..Self::new(order_id)
+        }
+    }
+}
+
+/// What happened to an amended order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AmendOutcome {
+    /// The order stayed where it was in the queue
+    pub priority_kept: bool,
+    /// The amended order's fate; an amend that loses priority is matched
+    /// again like a new order and may trade straight away
+    pub outcome: SubmitOutcome,
+}
+
+impl AmendOutcome {
+    pub fn rejected(order_id: Uuid, reason: RejectReason) -> Self {
+        Self {
+            priority_kept: false,
+            outcome: SubmitOutcome::rejected(order_id, reason),
+        }
+    }
+}
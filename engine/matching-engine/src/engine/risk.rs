@@ -1,8 +1,199 @@
-use crate::models::order::Order;
+use crate::engine::error::EngineError;
+use crate::engine::matching::MatchingEngine;
+use crate::models::{order::Order, order_type::OrderType, price::Price, side::Side};
+use serde::{Serialize, Deserialize};
 
-pub fn validate(order: &Order) -> Result<(), &'static str> {
+const BPS_DENOMINATOR: u128 = 10_000;
+
+pub fn validate(order: &Order) -> Result<(), EngineError> {
     if order.quantity == 0 {
-        return Err("Invalid quantity");
+        return Err(EngineError::InvalidQuantity);
+    }
+    if order.order_type.is_market() && order.time_in_force.is_post_only() {
+        return Err(EngineError::PostOnlyMarketOrder);
+    }
+    if order.order_type.stop_price().is_some_and(|stop| stop.0 == 0) {
+        return Err(EngineError::InvalidStopPrice);
+    }
+    if let Some(display) = order.display_quantity {
+        if display == 0 {
+            return Err(EngineError::InvalidDisplayQuantity);
+        }
+        if order.order_type.is_market() {
+            return Err(EngineError::IcebergMarketOrder);
+        }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// What a pre-trade check may look at besides the order
+pub struct RiskContext<'a> {
+    pub engine: &'a MatchingEngine,
+    /// Whether the wallet's kill switch is engaged
+    pub killed: bool,
+    /// Open orders the wallet already has in the market, not counting any
+    /// the order replaces
+    pub open_orders: usize,
+}
+
+/// One pre-trade check, run on an order before it can reserve funds or
+/// reach the book
+pub trait RiskCheck {
+    fn check(&self, order: &Order, context: &RiskContext) -> Result<(), EngineError>;
+}
+
+/// Run `checks` in order, stopping at the first that refuses the order
+pub fn run<'a>(checks: impl IntoIterator<Item = &'a dyn RiskCheck>, order: &Order, context: &RiskContext) -> Result<(), EngineError> {
+    checks.into_iter().try_for_each(|check| check.check(order, context))
+}
+
+/// Pre-trade limits for one market; each is only checked when set
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct RiskLimits {
+    #[serde(default)]
+    pub price_collar: Option<PriceCollar>,
+    #[serde(default)]
+    pub max_quantity: Option<MaxQuantity>,
+    #[serde(default)]
+    pub max_notional: Option<MaxNotional>,
+    #[serde(default)]
+    pub max_open_orders: Option<MaxOpenOrders>,
+}
+
+impl RiskLimits {
+    pub fn check(&self) -> Result<(), EngineError> {
+        let zero = self.price_collar.is_some_and(|collar| collar.bps == 0)
+            || self.max_quantity.is_some_and(|MaxQuantity(max)| max == 0)
+            || self.max_notional.is_some_and(|MaxNotional(max)| max == 0)
+            || self.max_open_orders.is_some_and(|MaxOpenOrders(max)| max == 0);
+        if zero {
+            return Err(EngineError::InvalidRiskLimit);
+        }
+        Ok(())
+    }
+
+    /// Every check an order in the market goes through, in the order they run
+    pub fn checks(&self) -> impl Iterator<Item = &dyn RiskCheck> {
+        [
+            Some(&KillSwitch as &dyn RiskCheck),
+            self.max_open_orders.as_ref().map(|check| check as &dyn RiskCheck),
+            self.max_quantity.as_ref().map(|check| check as &dyn RiskCheck),
+            self.price_collar.as_ref().map(|check| check as &dyn RiskCheck),
+            self.max_notional.as_ref().map(|check| check as &dyn RiskCheck),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+/// Refuses every order of a wallet whose kill switch is engaged
+pub struct KillSwitch;
+
+impl RiskCheck for KillSwitch {
+    fn check(&self, _order: &Order, context: &RiskContext) -> Result<(), EngineError> {
+        if context.killed {
+            return Err(EngineError::KillSwitchEngaged);
+        }
+        Ok(())
+    }
+}
+
+/// Price a collar is measured from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CollarReference {
+    #[default]
+    LastTrade,
+    /// Halfway between the best bid and offer
+    Mid,
+}
+
+/// Refuses orders priced more than `bps` away from the reference price in
+/// either direction; there is nothing to check until a reference exists.
+/// Market orders are measured at the worst price they could reach now, so
+/// a thin book cannot sweep them far away; stops are only priced on trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceCollar {
+    pub reference: CollarReference,
+    pub bps: u32,
+}
+
+impl RiskCheck for PriceCollar {
+    fn check(&self, order: &Order, context: &RiskContext) -> Result<(), EngineError> {
+        let engine = context.engine;
+        let price = match order.order_type {
+            OrderType::Market | OrderType::ProtectedMarket(_) => engine.sweep_price(order),
+            _ => limit_price(order),
+        };
+        let Some(price) = price else {
+            return Ok(());
+        };
+        let reference = match self.reference {
+            CollarReference::LastTrade => engine.last_trade_price,
+            CollarReference::Mid => engine.orderbook
+                .best_price(Side::Buy)
+                .zip(engine.orderbook.best_price(Side::Sell))
+                .map(|(bid, ask)| Price(((bid.0 as u128 + ask.0 as u128) / 2) as u64)),
+        };
+        let Some(reference) = reference else {
+            return Ok(());
+        };
+
+        let distance = price.0.abs_diff(reference.0) as u128;
+        if distance * BPS_DENOMINATOR > reference.0 as u128 * self.bps as u128 {
+            return Err(EngineError::PriceOutsideCollar);
+        }
+        Ok(())
+    }
+}
+
+/// Largest quantity a single order may have, iceberg reserve included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaxQuantity(pub u64);
+
+impl RiskCheck for MaxQuantity {
+    fn check(&self, order: &Order, _context: &RiskContext) -> Result<(), EngineError> {
+        if order.total_quantity() > self.0 {
+            return Err(EngineError::OrderQuantityLimit);
+        }
+        Ok(())
+    }
+}
+
+/// Largest price × quantity a single order may have. Orders without a limit
+/// price are valued at the last trade price and pass while there is none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaxNotional(pub u128);
+
+impl RiskCheck for MaxNotional {
+    fn check(&self, order: &Order, context: &RiskContext) -> Result<(), EngineError> {
+        let Some(price) = limit_price(order).or(context.engine.last_trade_price) else {
+            return Ok(());
+        };
+        // Both factors fit in 64 bits, so the product cannot overflow
+        if price.0 as u128 * order.total_quantity() as u128 > self.0 {
+            return Err(EngineError::OrderNotionalLimit);
+        }
+        Ok(())
+    }
+}
+
+/// Most orders a wallet may have open in the market at once, untriggered stops included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaxOpenOrders(pub usize);
+
+impl RiskCheck for MaxOpenOrders {
+    fn check(&self, _order: &Order, context: &RiskContext) -> Result<(), EngineError> {
+        if context.open_orders >= self.0 {
+            return Err(EngineError::OpenOrderLimit);
+        }
+        Ok(())
+    }
+}
+
+/// The order's own limit price, if it has one
+fn limit_price(order: &Order) -> Option<Price> {
+    match order.order_type {
+        OrderType::Limit | OrderType::StopLimit { .. } => Some(order.price),
+        OrderType::Market | OrderType::ProtectedMarket(_) | OrderType::Stop { .. } => None,
+    }
+}
@@ -0,0 +1,51 @@
+use crate::engine::error::EngineError;
+use serde::{Serialize, Deserialize};
+
+/// Where a market is in its trading day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TradingState {
+    /// Call period before the open or a reopening: orders are collected
+    /// without matching and uncrossed when the market moves to continuous
+    PreOpen,
+    /// Normal continuous matching
+    #[default]
+    Continuous,
+    /// Frozen during an incident: nothing is accepted until the market resumes
+    Halted,
+    /// Participants may pull orders but not add them
+    CancelOnly,
+    /// Permanently closed; the book has been emptied
+    Delisted,
+}
+
+impl TradingState {
+    pub fn accepts_orders(self) -> bool {
+        matches!(self, TradingState::PreOpen | TradingState::Continuous)
+    }
+
+    pub fn accepts_cancels(self) -> bool {
+        matches!(self, TradingState::PreOpen | TradingState::Continuous | TradingState::CancelOnly)
+    }
+
+    /// Check a move to `next` is allowed
+    pub fn transition(self, next: TradingState) -> Result<(), EngineError> {
+        if self == next {
+            return Err(EngineError::AlreadyInState);
+        }
+        if self == TradingState::Delisted {
+            return Err(EngineError::MarketUnavailable(self));
+        }
+        Ok(())
+    }
+
+    /// Reason an order or cancel is refused in this state
+    pub fn rejection(self) -> &'static str {
+        match self {
+            TradingState::PreOpen => "Market is not open",
+            TradingState::Continuous => "Market is open",
+            TradingState::Halted => "Market is halted",
+            TradingState::CancelOnly => "Market is cancel-only",
+            TradingState::Delisted => "Market is delisted",
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use crate::models::{order::Order, side::Side, price::Price};
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+/// Untriggered stop and stop-limit orders, kept off the visible book
+#[derive(Default, Serialize, Deserialize)]
+pub struct TriggerBook {
+    /// Buy stops fire when the last trade price rises to their stop price
+    pub buys: BTreeMap<Price, VecDeque<Order>>,
+    /// Sell stops fire when the last trade price falls to their stop price
+    pub sells: BTreeMap<Price, VecDeque<Order>>,
+    pub index: HashMap<Uuid, (Price, Side)>,
+}
+
+impl TriggerBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, order: Order) {
+        let stop = order.order_type.stop_price().expect("only stop orders are parked");
+        self.index.insert(order.id, (stop, order.side));
+
+        let book = match order.side {
+            Side::Buy => &mut self.buys,
+            Side::Sell => &mut self.sells,
+        };
+
+        book.entry(stop)
+            .or_insert_with(VecDeque::new)
+            .push_back(order);
+    }
+
+    pub fn remove(&mut self, order_id: Uuid) -> Option<Order> {
+        let (stop, side) = self.index.remove(&order_id)?;
+        let book = match side {
+            Side::Buy => &mut self.buys,
+            Side::Sell => &mut self.sells,
+        };
+
+        let queue = book.get_mut(&stop)?;
+        let position = queue.iter().position(|o| o.id == order_id)?;
+        let order = queue.remove(position);
+        if queue.is_empty() {
+            book.remove(&stop);
+        }
+        order
+    }
+
+    /// Swap in a changed version of a parked order, keeping its place; the
+    /// stop price and side must be unchanged
+    pub fn update(&mut self, order: Order) {
+        let Some((stop, side)) = self.index.get(&order.id) else {
+            return;
+        };
+        let book = match side {
+            Side::Buy => &mut self.buys,
+            Side::Sell => &mut self.sells,
+        };
+        if let Some(parked) = book.get_mut(stop).and_then(|queue| queue.iter_mut().find(|o| o.id == order.id)) {
+            *parked = order;
+        }
+    }
+
+    pub fn get(&self, order_id: Uuid) -> Option<&Order> {
+        let (stop, side) = self.index.get(&order_id)?;
+        let book = match side {
+            Side::Buy => &self.buys,
+            Side::Sell => &self.sells,
+        };
+        book.get(stop)?.iter().find(|o| o.id == order_id)
+    }
+
+    /// Take the next stop that `last` has reached
+    ///
+    /// Buy stops go first, lowest stop price first, then sell stops from the
+    /// highest stop price down; orders at the same stop price fire in arrival
+    /// order. Callers pop one at a time so that each triggered order's fills
+    /// are seen before the next stop is evaluated.
+    pub fn pop_triggered(&mut self, last: Price) -> Option<Order> {
+        let (book, stop) = if let Some(stop) = self.buys.keys().next().copied().filter(|s| *s <= last) {
+            (&mut self.buys, stop)
+        } else if let Some(stop) = self.sells.keys().next_back().copied().filter(|s| *s >= last) {
+            (&mut self.sells, stop)
+        } else {
+            return None;
+        };
+
+        let queue = book.get_mut(&stop)?;
+        let order = queue.pop_front()?;
+        if queue.is_empty() {
+            book.remove(&stop);
+        }
+        self.index.remove(&order.id);
+        Some(order)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
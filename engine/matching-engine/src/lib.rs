@@ -4,3 +4,5 @@ pub mod api;
 pub mod persistence;
 pub mod metrics;
 
+#[cfg(test)]
+mod tests;
@@ -1,15 +1,12 @@
 use tonic::transport::Server;
-use tracing_subscriber;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
-mod engine;
-mod models;
-mod api;
-mod persistence;
-mod metrics;
-
-use api::grpc::{GrpcEngine, engine_proto::matching_engine_server::MatchingEngineServer};
-use engine::market::MarketRegistry;
+use matching_engine::persistence;
+use matching_engine::api::session::SessionConfig;
+use matching_engine::api::grpc::{GrpcEngine, engine_proto::matching_engine_server::MatchingEngineServer};
+use matching_engine::engine::{config::MarketConfig, market::MarketRegistry};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -22,7 +19,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!("Starting matching engine on {}", addr);
 
     // Load snapshot if exists, otherwise start fresh
-    let registry = if Path::new("snapshot.json").exists() {
+    let mut registry = if Path::new("snapshot.json").exists() {
         match persistence::snapshot::load(Path::new("snapshot.json")) {
             Ok(reg) => {
                 tracing::info!("Loaded snapshot from disk");
@@ -38,10 +35,53 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         MarketRegistry::new()
     };
 
-    let engine = GrpcEngine {
-        registry: tokio::sync::Mutex::new(registry),
+    // Register configured markets; ones restored from the snapshot keep their config
+    if Path::new("markets.json").exists() {
+        let markets: Vec<MarketConfig> = serde_json::from_str(&std::fs::read_to_string("markets.json")?)?;
+        for config in markets {
+            if registry.get_market(&config.market).is_none() {
+                let market = config.market.clone();
+                match registry.register(config) {
+                    Ok(()) => tracing::info!("Registered market {}", market),
+                    Err(e) => tracing::warn!("Failed to register market {}: {}", market, e),
+                }
+            }
+        }
+    }
+
+    // Order-entry session timing, overridable in milliseconds
+    let defaults = SessionConfig::default();
+    let millis = |name: &str| std::env::var(name).ok().and_then(|v| v.parse().ok()).map(Duration::from_millis);
+    let session_config = SessionConfig {
+        heartbeat_timeout: millis("SESSION_HEARTBEAT_TIMEOUT_MS").unwrap_or(defaults.heartbeat_timeout),
+        grace_period: millis("SESSION_GRACE_PERIOD_MS").unwrap_or(defaults.grace_period),
     };
 
+    // Identities allowed to deposit and engage kill switches, comma separated
+    let operators = std::env::var("OPERATOR_IDENTITIES").unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|identity| !identity.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    let engine = Arc::new(GrpcEngine {
+        registry: Arc::new(tokio::sync::Mutex::new(registry)),
+        session_config,
+        operators: Arc::new(operators),
+        ..Default::default()
+    });
+
+    // Engine clock: expires good-till-date orders between submissions
+    let clock = engine.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(100));
+        loop {
+            interval.tick().await;
+            clock.tick().await;
+        }
+    });
+
     // Periodic snapshot task (every 60 seconds in production)
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
@@ -55,7 +95,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!("gRPC server listening on {}", addr);
 
     Server::builder()
-        .add_service(MatchingEngineServer::new(engine))
+        .add_service(MatchingEngineServer::from_arc(engine))
         .serve(addr)
         .await?;
 
@@ -0,0 +1,41 @@
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+use super::{order::Order, side::Side, price::Price};
+
+/// A change to the price or size of a live order, resting or waiting to
+/// trigger
+///
+/// `wallet` and `side` must match the order being amended; they guard
+/// against amending somebody else's order or the wrong leg of a quote.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Amendment {
+    pub order_id: Uuid,
+    pub market: String,
+    pub wallet: String,
+    pub side: Side,
+    /// New limit price; `None` keeps the current one
+    pub price: Option<Price>,
+    /// New open quantity, shown and hidden together; `None` keeps the current one
+    pub quantity: Option<u64>,
+    pub timestamp: u64,
+}
+
+impl Amendment {
+    /// The order as it would stand after this amendment
+    pub fn apply(&self, order: &Order) -> Order {
+        Order {
+            price: self.price.unwrap_or(order.price),
+            quantity: self.quantity.unwrap_or(order.total_quantity()),
+            hidden_quantity: 0,
+            timestamp: self.timestamp,
+            ..order.clone()
+        }
+    }
+
+    /// Whether the amended order may keep its place in the queue: only a
+    /// size decrease at the same price does
+    pub fn keeps_priority(&self, order: &Order) -> bool {
+        self.price.is_none_or(|price| price == order.price)
+            && self.quantity.is_none_or(|quantity| quantity <= order.total_quantity())
+    }
+}
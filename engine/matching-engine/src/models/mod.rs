@@ -1,5 +1,17 @@
 pub mod order;
+pub mod amendment;
+pub mod order_type;
 pub mod trade;
 pub mod price;
+pub mod self_trade;
 pub mod side;
+pub mod time_in_force;
 
+pub use order::Order;
+pub use amendment::Amendment;
+pub use order_type::{OrderType, Protection};
+pub use trade::Trade;
+pub use price::Price;
+pub use self_trade::SelfTradePrevention;
+pub use side::Side;
+pub use time_in_force::TimeInForce;
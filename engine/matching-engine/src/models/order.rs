@@ -1,6 +1,6 @@
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
-use super::{side::Side, price::Price};
+use super::{side::Side, price::Price, order_type::{OrderType, Protection}, time_in_force::TimeInForce, self_trade::SelfTradePrevention};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
@@ -11,5 +11,71 @@ pub struct Order {
     pub price: Price,
     pub quantity: u64,
     pub timestamp: u64,
+    #[serde(default)]
+    pub order_type: OrderType,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    /// Iceberg orders: the most that is ever shown on the book at once
+    #[serde(default)]
+    pub display_quantity: Option<u64>,
+    /// Iceberg orders: reserve not yet shown on the book, managed by the engine
+    #[serde(default)]
+    pub hidden_quantity: u64,
+    #[serde(default)]
+    pub self_trade_prevention: SelfTradePrevention,
+    /// Caller's own reference, unique per wallet; resubmitting it returns the
+    /// original result instead of placing a second order
+    #[serde(default)]
+    pub client_order_id: Option<String>,
 }
 
+impl Order {
+    /// Move everything above the display size into the hidden reserve
+    pub fn conceal(&mut self) {
+        if let Some(display) = self.display_quantity {
+            if self.quantity > display {
+                self.hidden_quantity += self.quantity - display;
+                self.quantity = display;
+            }
+        }
+    }
+
+    /// Turn a stop whose trigger was reached into the order it stands for. A
+    /// stop buy's price caps what it pays once triggered, as that is what
+    /// its funds were reserved against.
+    pub fn trigger(&mut self) {
+        self.order_type = match self.order_type {
+            OrderType::Stop { .. } if self.side == Side::Buy && self.price.0 > 0 => {
+                OrderType::ProtectedMarket(Protection::WorstPrice(self.price))
+            }
+            other => other.triggered(),
+        };
+    }
+
+    /// Shown and hidden quantity together
+    pub fn total_quantity(&self) -> u64 {
+        self.quantity + self.hidden_quantity
+    }
+
+    /// Take `qty` off the order, drawing down the hidden reserve before the
+    /// displayed slice so the slice keeps its place in the queue
+    pub fn reduce(&mut self, qty: u64) {
+        let from_hidden = qty.min(self.hidden_quantity);
+        self.hidden_quantity -= from_hidden;
+        self.quantity = self.quantity.saturating_sub(qty - from_hidden);
+    }
+
+    /// Refill an exhausted iceberg slice from the hidden reserve; returns
+    /// false when there is nothing left to show
+    pub fn replenish(&mut self) -> bool {
+        match self.display_quantity {
+            Some(display) if self.quantity == 0 && self.hidden_quantity > 0 => {
+                let slice = display.min(self.hidden_quantity);
+                self.hidden_quantity -= slice;
+                self.quantity = slice;
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use serde::{Serialize, Deserialize};
+use super::{side::Side, price::Price};
+
+const BPS_DENOMINATOR: u128 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OrderType {
+    /// Matches up to `price` and rests any remainder on the book
+    #[default]
+    Limit,
+    /// Sweeps the opposite side at any price; the remainder is never rested
+    Market,
+    /// Market order that stops sweeping once the next level breaches the protection bound
+    ProtectedMarket(Protection),
+    /// Becomes a market order once the last trade price reaches `trigger`
+    Stop { trigger: Price },
+    /// Becomes a limit order at the order's price once the last trade price reaches `trigger`
+    StopLimit { trigger: Price },
+}
+
+impl OrderType {
+    pub fn is_market(&self) -> bool {
+        matches!(self, OrderType::Market | OrderType::ProtectedMarket(_) | OrderType::Stop { .. })
+    }
+
+    pub fn stop_price(&self) -> Option<Price> {
+        match *self {
+            OrderType::Stop { trigger } | OrderType::StopLimit { trigger } => Some(trigger),
+            _ => None,
+        }
+    }
+
+    /// The order type a stop turns into once triggered
+    pub fn triggered(self) -> Self {
+        match self {
+            OrderType::Stop { .. } => OrderType::Market,
+            OrderType::StopLimit { .. } => OrderType::Limit,
+            other => other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Protection {
+    /// Worst price the order may execute at
+    WorstPrice(Price),
+    /// Maximum distance from the best opposite price at arrival, in basis points
+    SlippageBps(u32),
+}
+
+impl Protection {
+    /// Resolve the protection into a limit price for an order on `side`,
+    /// given the best opposite price when the order arrives
+    pub fn bound(&self, side: Side, reference: Price) -> Price {
+        match *self {
+            Protection::WorstPrice(price) => price,
+            Protection::SlippageBps(bps) => {
+                let reference = reference.0 as u128;
+                let bps = bps as u128;
+                let bound = match side {
+                    Side::Buy => reference * (BPS_DENOMINATOR + bps) / BPS_DENOMINATOR,
+                    // Round up so a seller never accepts more slippage than requested
+                    Side::Sell => (reference * BPS_DENOMINATOR.saturating_sub(bps))
+                        .div_ceil(BPS_DENOMINATOR),
+                };
+                Price(bound.min(u64::MAX as u128) as u64)
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Serialize, Deserialize};
+
+/// What to do when an order would trade against a resting order from the
+/// same wallet. The incoming (taker) order's mode is the one applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SelfTradePrevention {
+    /// Self-trades are allowed
+    #[default]
+    None,
+    /// Cancel the remainder of the incoming order
+    CancelNewest,
+    /// Cancel the resting order and keep matching
+    CancelOldest,
+    /// Cancel both the resting order and the remainder of the incoming order
+    CancelBoth,
+    /// Reduce both orders by the smaller quantity, cancelling whichever reaches zero
+    DecrementAndCancel,
+}
+
+impl SelfTradePrevention {
+    /// Quantities to cancel from the incoming and resting order respectively
+    /// when they meet with the given remaining quantities
+    pub fn cancellations(self, taker: u64, maker: u64) -> (u64, u64) {
+        match self {
+            SelfTradePrevention::None => (0, 0),
+            SelfTradePrevention::CancelNewest => (taker, 0),
+            SelfTradePrevention::CancelOldest => (0, maker),
+            SelfTradePrevention::CancelBoth => (taker, maker),
+            SelfTradePrevention::DecrementAndCancel => {
+                let decrement = taker.min(maker);
+                (decrement, decrement)
+            }
+        }
+    }
+}
@@ -6,3 +6,11 @@ pub enum Side {
     Sell,
 }
 
+impl Side {
+    pub fn opposite(self) -> Self {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
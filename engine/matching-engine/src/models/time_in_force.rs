@@ -0,0 +1,36 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TimeInForce {
+    /// Good till cancelled: any remainder rests on the book
+    #[default]
+    Gtc,
+    /// Immediate or cancel: fills what it can, the remainder is cancelled
+    Ioc,
+    /// Fill or kill: fills completely in one go or not at all
+    Fok,
+    /// Good till date: rests until the engine clock reaches `expires_at` (unix millis)
+    Gtd { expires_at: u64 },
+    /// Only ever adds liquidity; rejected if it would cross on arrival
+    PostOnly,
+    /// Only ever adds liquidity; repriced one tick behind the best opposite price if it would cross
+    PostOnlySlide,
+}
+
+impl TimeInForce {
+    pub fn is_post_only(self) -> bool {
+        matches!(self, TimeInForce::PostOnly | TimeInForce::PostOnlySlide)
+    }
+
+    /// Whether an unfilled remainder may rest on the book
+    pub fn rests(self) -> bool {
+        !matches!(self, TimeInForce::Ioc | TimeInForce::Fok)
+    }
+
+    pub fn expires_at(self) -> Option<u64> {
+        match self {
+            TimeInForce::Gtd { expires_at } => Some(expires_at),
+            _ => None,
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
 use super::price::Price;
+use super::side::Side;
 
+/// An execution between two orders
+///
+/// Continuous trades name the resting order as maker and the incoming order
+/// as taker. An auction uncross has no aggressor, so `taker_side` is unset and
+/// by convention the buy order is reported as maker and the sell order as taker;
+/// both then pay their taker rate.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
     pub market: String,
@@ -10,4 +17,28 @@ pub struct Trade {
     pub price: Price,
     pub quantity: u64,
     pub sequence: u64,
+    /// Set on trades produced by an auction uncross; all trades of one uncross share it
+    #[serde(default)]
+    pub auction_sequence: Option<u64>,
+    /// Side of the order that took liquidity
+    #[serde(default)]
+    pub taker_side: Option<Side>,
+    #[serde(default)]
+    pub maker_order: Uuid,
+    #[serde(default)]
+    pub taker_order: Uuid,
+    #[serde(default)]
+    pub maker_wallet: String,
+    #[serde(default)]
+    pub taker_wallet: String,
+    /// Engine time of the execution in unix millis
+    #[serde(default)]
+    pub executed_at: u64,
+    /// Fees charged to each side in `fee_currency`; negative for a rebate
+    #[serde(default)]
+    pub maker_fee: i128,
+    #[serde(default)]
+    pub taker_fee: i128,
+    #[serde(default)]
+    pub fee_currency: String,
 }
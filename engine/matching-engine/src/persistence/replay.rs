@@ -1,13 +1,41 @@
+use crate::engine::config::MarketConfig;
+use crate::engine::ledger::{JournalEntry, TransferKind};
 use crate::engine::market::MarketRegistry;
 use crate::models::order::Order;
+use serde::Serialize;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 use std::path::Path;
 
+/// One line of the replay log. Orders need funds to rest, so deposits and
+/// withdrawals go into the same log, in the order the ledger applied them.
+#[derive(Debug, Clone)]
+pub enum LogEntry {
+    Transfer(JournalEntry),
+    Order(Order),
+}
+
+impl LogEntry {
+    /// Read a line written by `append_to_log` or `append_transfer_to_log`.
+    /// Not an untagged enum, as those cannot hold the ledger's u128 amounts.
+    pub fn parse(line: &str) -> serde_json::Result<Self> {
+        match serde_json::from_str(line) {
+            Ok(transfer) => Ok(LogEntry::Transfer(transfer)),
+            Err(_) => serde_json::from_str(line).map(LogEntry::Order),
+        }
+    }
+}
+
 /// Replay events from a log file to reconstruct state
 /// This is crucial for disaster recovery and audit compliance
-pub fn replay_from_log(log_path: &Path) -> io::Result<MarketRegistry> {
+/// The log holds orders and transfers, so the markets they trade on are registered up front
+pub fn replay_from_log(log_path: &Path, markets: Vec<MarketConfig>) -> io::Result<MarketRegistry> {
     let mut registry = MarketRegistry::new();
+    for config in markets {
+        registry.register(config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    }
+
     let file = File::open(log_path)?;
     let reader = BufReader::new(file);
 
@@ -17,21 +45,41 @@ pub fn replay_from_log(log_path: &Path) -> io::Result<MarketRegistry> {
             continue;
         }
 
-        let order: Order = serde_json::from_str(&line)
+        let entry = LogEntry::parse(&line)
             .map_err(|e| io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!("Line {}: {}", line_num + 1, e)
             ))?;
 
-        registry.submit(order);
+        // Entries that were rejected live are rejected again, so this is not fatal
+        let result = match entry {
+            LogEntry::Transfer(transfer) => match transfer.kind {
+                TransferKind::Deposit => registry.deposit(&transfer.wallet, &transfer.asset, transfer.amount, transfer.timestamp).map(drop),
+                TransferKind::Withdrawal => registry.withdraw(&transfer.wallet, &transfer.asset, transfer.amount, transfer.timestamp).map(drop),
+            },
+            LogEntry::Order(order) => registry.submit(order).map(drop),
+        };
+        if let Err(e) = result {
+            tracing::debug!("Replayed entry on line {} rejected: {}", line_num + 1, e);
+        }
     }
 
-    tracing::info!("Replayed orders from log");
+    tracing::info!("Replayed orders and transfers from log");
     Ok(registry)
 }
 
 /// Append an order to the replay log for durability
 pub fn append_to_log(order: &Order, log_path: &Path) -> io::Result<()> {
+    append_line(order, log_path)
+}
+
+/// Append a deposit or withdrawal to the replay log, once the ledger has
+/// applied it
+pub fn append_transfer_to_log(transfer: &JournalEntry, log_path: &Path) -> io::Result<()> {
+    append_line(transfer, log_path)
+}
+
+fn append_line(entry: &impl Serialize, log_path: &Path) -> io::Result<()> {
     use std::fs::OpenOptions;
     use std::io::Write;
 
@@ -40,8 +88,8 @@ pub fn append_to_log(order: &Order, log_path: &Path) -> io::Result<()> {
         .append(true)
         .open(log_path)?;
 
-    let json = serde_json::to_string(order)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let json = serde_json::to_string(entry)
+        .map_err(io::Error::other)?;
     
     writeln!(file, "{}", json)?;
     file.sync_all()?; // Force flush to disk for durability
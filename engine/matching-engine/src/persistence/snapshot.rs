@@ -7,7 +7,7 @@ use std::io;
 /// This enables crash recovery and state reconstruction
 pub fn save(registry: &MarketRegistry, path: &Path) -> io::Result<()> {
     let data = serde_json::to_string_pretty(registry)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        .map_err(io::Error::other)?;
     
     // Write atomically by using a temp file
     let temp_path = path.with_extension("tmp");
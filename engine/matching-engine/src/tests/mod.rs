@@ -1,7 +1,26 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
+    use crate::api::grpc::GrpcEngine;
+    use crate::api::grpc::engine_proto::{self, session_request, session_response, SessionRequest, SessionResponse};
+    use crate::api::grpc::engine_proto::matching_engine_client::MatchingEngineClient;
+    use crate::api::grpc::engine_proto::matching_engine_server::MatchingEngineServer;
+    use crate::api::session::{Sessions, SessionConfig};
+    use crate::engine::market::MarketRegistry;
     use crate::engine::matching::MatchingEngine;
-    use crate::models::{order::Order, side::Side, price::Price};
+    use crate::engine::config::{MarketConfig, MatchingMode, BatchArrival};
+    use crate::engine::error::EngineError;
+    use crate::engine::events::EngineEvent;
+    use crate::engine::fees::{self, FeeRates, FeeSchedule, FeeTier, FEE_VOLUME_WINDOW_DAYS};
+    use crate::engine::mmp::MmpLimits;
+    use crate::engine::client_ids::CLIENT_ORDER_ID_WINDOW_MS;
+    use crate::engine::ledger::{Balance, TransferKind};
+    use crate::engine::lifecycle::{OrderReport, OrderStatus, ORDER_RECORD_RETENTION_MS};
+    use crate::engine::risk::{RiskLimits, PriceCollar, CollarReference, MaxQuantity, MaxNotional, MaxOpenOrders};
+    use crate::engine::outcome::{RejectReason, CancelResult, Closed};
+    use crate::engine::state::TradingState;
+    use crate::persistence::replay;
+    use crate::models::{order::Order, amendment::Amendment, order_type::{OrderType, Protection}, time_in_force::TimeInForce, self_trade::SelfTradePrevention, side::Side, price::Price};
     use uuid::Uuid;
 
     fn create_order(side: Side, price: u64, quantity: u64) -> Order {
@@ -13,6 +32,26 @@ mod tests {
             price: Price(price),
             quantity,
             timestamp: 0,
+            order_type: OrderType::Limit,
+            time_in_force: TimeInForce::Gtc,
+            display_quantity: None,
+            hidden_quantity: 0,
+            self_trade_prevention: SelfTradePrevention::None,
+            client_order_id: None,
+        }
+    }
+
+    fn create_order_tif(side: Side, price: u64, quantity: u64, time_in_force: TimeInForce) -> Order {
+        Order {
+            time_in_force,
+            ..create_order(side, price, quantity)
+        }
+    }
+
+    fn create_market_order(side: Side, quantity: u64, order_type: OrderType) -> Order {
+        Order {
+            order_type,
+            ..create_order(side, 0, quantity)
         }
     }
 
@@ -22,12 +61,12 @@ mod tests {
 
         // Add a sell order at 50000
         let sell = create_order(Side::Sell, 50000, 10);
-        let trades = engine.submit(sell);
+        let trades = engine.submit(sell).trades;
         assert_eq!(trades.len(), 0); // No match yet
 
         // Add a buy order at 50000 (should match)
         let buy = create_order(Side::Buy, 50000, 5);
-        let trades = engine.submit(buy);
+        let trades = engine.submit(buy).trades;
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].quantity, 5);
         assert_eq!(trades[0].price.0, 50000);
@@ -47,7 +86,7 @@ mod tests {
 
         // Buy should match with first order (time priority)
         let buy = create_order(Side::Buy, 50000, 3);
-        let trades = engine.submit(buy);
+        let trades = engine.submit(buy).trades;
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].sell_order, sell1_id);
     }
@@ -60,14 +99,14 @@ mod tests {
         engine.submit(sell);
 
         let buy = create_order(Side::Buy, 50000, 6);
-        let trades = engine.submit(buy);
+        let trades = engine.submit(buy).trades;
         
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].quantity, 6);
 
         // Remaining quantity should still be in book
         let buy2 = create_order(Side::Buy, 50000, 4);
-        let trades2 = engine.submit(buy2);
+        let trades2 = engine.submit(buy2).trades;
         assert_eq!(trades2.len(), 1);
         assert_eq!(trades2[0].quantity, 4);
     }
@@ -80,7 +119,7 @@ mod tests {
         engine.submit(sell);
 
         let buy = create_order(Side::Buy, 49999, 10);
-        let trades = engine.submit(buy);
+        let trades = engine.submit(buy).trades;
         
         assert_eq!(trades.len(), 0); // No match, price too low
     }
@@ -93,11 +132,11 @@ mod tests {
         let order_id = order.id;
         engine.submit(order);
 
-        engine.cancel(order_id);
+        engine.cancel(order_id, "test-wallet");
 
         // Try to match - should not find the cancelled order
         let sell = create_order(Side::Sell, 50000, 10);
-        let trades = engine.submit(sell);
+        let trades = engine.submit(sell).trades;
         assert_eq!(trades.len(), 0);
     }
 
@@ -109,10 +148,10 @@ mod tests {
         engine.submit(sell);
 
         let buy1 = create_order(Side::Buy, 50000, 3);
-        let trades1 = engine.submit(buy1);
+        let trades1 = engine.submit(buy1).trades;
         
         let buy2 = create_order(Side::Buy, 50000, 2);
-        let trades2 = engine.submit(buy2);
+        let trades2 = engine.submit(buy2).trades;
 
         assert_eq!(trades1[0].sequence, 1);
         assert_eq!(trades2[0].sequence, 2);
@@ -129,10 +168,1993 @@ mod tests {
 
         // Buy at 50150 should match with 50000 and 50100
         let buy = create_order(Side::Buy, 50150, 8);
-        let trades = engine.submit(buy);
+        let trades = engine.submit(buy).trades;
         
         assert_eq!(trades.len(), 2);
         assert_eq!(trades[0].price.0, 50000); // Best price first
         assert_eq!(trades[1].price.0, 50100);
     }
+
+    #[test]
+    fn test_market_order_sweeps_levels() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Sell, 50000, 5));
+        engine.submit(create_order(Side::Sell, 50100, 5));
+        engine.submit(create_order(Side::Sell, 60000, 5));
+
+        let buy = create_market_order(Side::Buy, 12, OrderType::Market);
+        let trades = engine.submit(buy).trades;
+
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[2].price.0, 60000);
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<u64>(), 12);
+    }
+
+    #[test]
+    fn test_market_order_never_rests() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Buy, 50000, 5));
+
+        let sell = create_market_order(Side::Sell, 8, OrderType::Market);
+        let trades = engine.submit(sell).trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, 5);
+
+        // The unfilled 3 must not be left on the book
+        assert!(engine.orderbook.best_price(Side::Sell).is_none());
+        assert!(engine.orderbook.best_price(Side::Buy).is_none());
+    }
+
+    #[test]
+    fn test_protected_market_worst_price() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Sell, 50000, 5));
+        engine.submit(create_order(Side::Sell, 50100, 5));
+        engine.submit(create_order(Side::Sell, 50200, 5));
+
+        let protection = Protection::WorstPrice(Price(50100));
+        let buy = create_market_order(Side::Buy, 15, OrderType::ProtectedMarket(protection));
+        let trades = engine.submit(buy).trades;
+
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[1].price.0, 50100);
+        assert!(engine.orderbook.best_price(Side::Buy).is_none());
+        assert_eq!(engine.orderbook.best_price(Side::Sell), Some(Price(50200)));
+    }
+
+    #[test]
+    fn test_protected_market_slippage_bps() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Buy, 10000, 5));
+        engine.submit(create_order(Side::Buy, 9950, 5));
+        engine.submit(create_order(Side::Buy, 9949, 5));
+
+        // 50 bps below the best bid of 10000 is 9950
+        let sell = create_market_order(
+            Side::Sell,
+            15,
+            OrderType::ProtectedMarket(Protection::SlippageBps(50)),
+        );
+        let trades = engine.submit(sell).trades;
+
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[1].price.0, 9950);
+        assert!(engine.orderbook.best_price(Side::Sell).is_none());
+    }
+
+    #[test]
+    fn test_ioc_cancels_remainder() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Sell, 50000, 4));
+
+        let buy = create_order_tif(Side::Buy, 50000, 10, TimeInForce::Ioc);
+        let outcome = engine.submit(buy);
+
+        assert_eq!(outcome.filled, 4);
+        assert_eq!(outcome.rested, 0);
+        assert_eq!(outcome.cancelled, 6);
+        assert!(engine.orderbook.best_price(Side::Buy).is_none());
+    }
+
+    #[test]
+    fn test_fok_rejects_without_touching_book() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Sell, 50000, 4));
+        engine.submit(create_order(Side::Sell, 50100, 4));
+        engine.submit(create_order(Side::Sell, 50200, 4));
+
+        // Only 8 is available at or below 50100
+        let buy = create_order_tif(Side::Buy, 50100, 10, TimeInForce::Fok);
+        let outcome = engine.submit(buy);
+
+        assert_eq!(outcome.rejected, Some(RejectReason::FillOrKillUnfillable));
+        assert!(outcome.trades.is_empty());
+        assert_eq!(engine.orderbook.levels(Side::Sell).map(|(_, q)| q).sum::<u64>(), 12);
+
+        let buy = create_order_tif(Side::Buy, 50200, 10, TimeInForce::Fok);
+        let outcome = engine.submit(buy);
+        assert_eq!(outcome.filled, 10);
+        assert_eq!(outcome.trades.len(), 3);
+    }
+
+    #[test]
+    fn test_post_only_rejects_when_crossing() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Sell, 50000, 5));
+
+        let buy = create_order_tif(Side::Buy, 50000, 5, TimeInForce::PostOnly);
+        let outcome = engine.submit(buy);
+        assert_eq!(outcome.rejected, Some(RejectReason::PostOnlyWouldCross));
+        assert!(engine.orderbook.best_price(Side::Buy).is_none());
+
+        let buy = create_order_tif(Side::Buy, 49999, 5, TimeInForce::PostOnly);
+        let outcome = engine.submit(buy);
+        assert_eq!(outcome.rested, 5);
+        assert_eq!(engine.orderbook.best_price(Side::Buy), Some(Price(49999)));
+    }
+
+    #[test]
+    fn test_post_only_slide_reprices() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Buy, 50000, 5));
+
+        let sell = create_order_tif(Side::Sell, 49000, 5, TimeInForce::PostOnlySlide);
+        let outcome = engine.submit(sell);
+
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.rested, 5);
+        assert_eq!(engine.orderbook.best_price(Side::Sell), Some(Price(50001)));
+    }
+
+    #[test]
+    fn test_gtd_order_expires_on_tick() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let buy = create_order_tif(Side::Buy, 50000, 5, TimeInForce::Gtd { expires_at: 1_000 });
+        let buy_id = buy.id;
+        engine.submit(buy);
+        engine.drain_events();
+
+        assert!(engine.tick(999).is_empty());
+        assert_eq!(engine.tick(1_000), vec![buy_id]);
+        assert!(engine.orderbook.best_price(Side::Buy).is_none());
+
+        let events = engine.drain_events();
+        assert!(matches!(
+            events.as_slice(),
+            [EngineEvent::OrderExpired { order_id, timestamp: 1_000, .. }] if *order_id == buy_id
+        ));
+    }
+
+    #[test]
+    fn test_gtd_expiry_follows_order_timestamps() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let sell = create_order_tif(Side::Sell, 50000, 5, TimeInForce::Gtd { expires_at: 1_000 });
+        engine.submit(sell);
+
+        // No tick in between: the arriving order's own timestamp advances the clock
+        let buy = Order { timestamp: 1_500, ..create_order(Side::Buy, 50000, 5) };
+        let outcome = engine.submit(buy);
+
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.rested, 5);
+        assert_eq!(engine.clock, 1_500);
+    }
+
+    #[test]
+    fn test_gtd_order_already_expired_is_rejected() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        engine.tick(2_000);
+
+        let buy = create_order_tif(Side::Buy, 50000, 5, TimeInForce::Gtd { expires_at: 1_000 });
+        let outcome = engine.submit(buy);
+
+        assert_eq!(outcome.rejected, Some(RejectReason::Expired));
+        assert!(engine.orderbook.best_price(Side::Buy).is_none());
+    }
+
+    #[test]
+    fn test_gtd_expiry_survives_snapshot() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let buy = create_order_tif(Side::Buy, 50000, 5, TimeInForce::Gtd { expires_at: 1_000 });
+        let buy_id = buy.id;
+        engine.submit(buy);
+
+        let json = serde_json::to_string(&engine).unwrap();
+        let mut restored: MatchingEngine = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.tick(1_000), vec![buy_id]);
+    }
+
+    #[test]
+    fn test_stop_order_triggers_on_last_trade() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Sell, 50000, 1));
+        engine.submit(create_order(Side::Sell, 50100, 5));
+
+        let stop = create_market_order(Side::Buy, 5, OrderType::Stop { trigger: Price(50000) });
+        let stop_id = stop.id;
+        let outcome = engine.submit(stop);
+        assert_eq!(outcome.parked, 5);
+        assert!(outcome.trades.is_empty());
+
+        // Trading at 50000 reaches the stop, which then lifts the 50100 offer
+        let outcome = engine.submit(create_order(Side::Buy, 50000, 1));
+        assert_eq!(outcome.triggered, vec![stop_id]);
+        assert_eq!(outcome.trades.len(), 2);
+        assert_eq!(outcome.trades[1].buy_order, stop_id);
+        assert_eq!(outcome.trades[1].price.0, 50100);
+        assert!(engine.stops.is_empty());
+    }
+
+    #[test]
+    fn test_stop_orders_cascade_in_order() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Sell, 100, 5));
+        engine.submit(create_order(Side::Sell, 101, 5));
+        engine.submit(create_order(Side::Sell, 102, 5));
+
+        let second = create_market_order(Side::Buy, 5, OrderType::Stop { trigger: Price(101) });
+        let second_id = second.id;
+        engine.submit(second);
+        let first = create_market_order(Side::Buy, 5, OrderType::Stop { trigger: Price(100) });
+        let first_id = first.id;
+        engine.submit(first);
+
+        let outcome = engine.submit(create_order(Side::Buy, 100, 1));
+
+        assert_eq!(outcome.triggered, vec![first_id, second_id]);
+        assert_eq!(outcome.trades.len(), 5);
+        assert_eq!(engine.last_trade_price, Some(Price(102)));
+        let sequences: Vec<u64> = outcome.trades.iter().map(|t| t.sequence).collect();
+        assert_eq!(sequences, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_stop_limit_rests_after_trigger() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_order(Side::Buy, 50000, 1));
+
+        let stop = Order {
+            order_type: OrderType::StopLimit { trigger: Price(50000) },
+            ..create_order(Side::Sell, 49900, 5)
+        };
+        engine.submit(stop);
+        engine.submit(create_order(Side::Sell, 50000, 1));
+
+        assert_eq!(engine.orderbook.best_price(Side::Sell), Some(Price(49900)));
+        assert_eq!(engine.orderbook.levels(Side::Sell).next(), Some((Price(49900), 5)));
+    }
+
+    #[test]
+    fn test_cancel_untriggered_stop() {
+        let mut registry = btc_registry();
+
+        registry.submit(create_order(Side::Sell, 50000, 5)).unwrap();
+        let stop = Order {
+            order_type: OrderType::StopLimit { trigger: Price(50000) },
+            ..create_order(Side::Buy, 50000, 5)
+        };
+        let stop_id = stop.id;
+        registry.submit(stop).unwrap();
+
+        registry.cancel("BTC-USD", stop_id, "test-wallet").unwrap();
+
+        let outcome = registry.submit(create_order(Side::Buy, 50000, 1)).unwrap();
+        assert!(outcome.triggered.is_empty());
+        assert!(registry.get_market("BTC-USD").unwrap().stops.is_empty());
+    }
+
+    fn create_iceberg(side: Side, price: u64, quantity: u64, display: u64) -> Order {
+        Order {
+            display_quantity: Some(display),
+            ..create_order(side, price, quantity)
+        }
+    }
+
+    #[test]
+    fn test_iceberg_only_displays_slice() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let outcome = engine.submit(create_iceberg(Side::Sell, 50000, 20, 5));
+        assert_eq!(outcome.rested, 20);
+
+        assert_eq!(engine.orderbook.levels(Side::Sell).collect::<Vec<_>>(), vec![(Price(50000), 5)]);
+        assert_eq!(engine.orderbook.best_order(Side::Sell).unwrap().hidden_quantity, 15);
+    }
+
+    #[test]
+    fn test_iceberg_refresh_loses_priority() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let iceberg = create_iceberg(Side::Sell, 50000, 15, 5);
+        let iceberg_id = iceberg.id;
+        engine.submit(iceberg);
+        let plain = create_order(Side::Sell, 50000, 5);
+        let plain_id = plain.id;
+        engine.submit(plain);
+        engine.drain_events();
+
+        let trades = engine.submit(create_order(Side::Buy, 50000, 7)).trades;
+
+        assert_eq!(trades.len(), 2);
+        assert_eq!((trades[0].sell_order, trades[0].quantity), (iceberg_id, 5));
+        assert_eq!((trades[1].sell_order, trades[1].quantity), (plain_id, 2));
+        assert_eq!(engine.orderbook.levels(Side::Sell).next(), Some((Price(50000), 8)));
+
+        let refreshed: Vec<_> = engine.drain_events().into_iter().filter_map(|e| match e {
+            EngineEvent::IcebergRefreshed { order_id, displayed_quantity, .. } => Some((order_id, displayed_quantity)),
+            _ => None,
+        }).collect();
+        assert_eq!(refreshed, vec![(iceberg_id, 5)]);
+    }
+
+    #[test]
+    fn test_iceberg_fully_consumed_across_slices() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_iceberg(Side::Buy, 50000, 12, 5));
+
+        let outcome = engine.submit(create_order_tif(Side::Sell, 50000, 12, TimeInForce::Fok));
+
+        let sizes: Vec<u64> = outcome.trades.iter().map(|t| t.quantity).collect();
+        assert_eq!(sizes, vec![5, 5, 2]);
+        assert!(engine.orderbook.best_price(Side::Buy).is_none());
+    }
+
+    fn create_stp_order(wallet: &str, side: Side, price: u64, quantity: u64, mode: SelfTradePrevention) -> Order {
+        Order {
+            wallet: wallet.to_string(),
+            self_trade_prevention: mode,
+            client_order_id: None,
+            ..create_order(side, price, quantity)
+        }
+    }
+
+    #[test]
+    fn test_stp_cancel_newest() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_stp_order("alice", Side::Sell, 50000, 5, SelfTradePrevention::None));
+
+        let buy = create_stp_order("alice", Side::Buy, 50000, 10, SelfTradePrevention::CancelNewest);
+        let outcome = engine.submit(buy);
+
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.cancelled, 10);
+        assert!(engine.orderbook.best_price(Side::Buy).is_none());
+        assert_eq!(engine.orderbook.levels(Side::Sell).next(), Some((Price(50000), 5)));
+    }
+
+    #[test]
+    fn test_stp_cancel_oldest_keeps_matching() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_stp_order("alice", Side::Sell, 50000, 5, SelfTradePrevention::None));
+        let other = create_stp_order("bob", Side::Sell, 50000, 5, SelfTradePrevention::None);
+        let other_id = other.id;
+        engine.submit(other);
+
+        let buy = create_stp_order("alice", Side::Buy, 50000, 7, SelfTradePrevention::CancelOldest);
+        let outcome = engine.submit(buy);
+
+        assert_eq!(outcome.trades.len(), 1);
+        assert_eq!(outcome.trades[0].sell_order, other_id);
+        assert_eq!((outcome.filled, outcome.rested, outcome.cancelled), (5, 2, 0));
+        assert!(engine.orderbook.best_price(Side::Sell).is_none());
+    }
+
+    #[test]
+    fn test_stp_cancel_both() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_stp_order("alice", Side::Buy, 50000, 5, SelfTradePrevention::None));
+
+        let sell = create_stp_order("alice", Side::Sell, 50000, 3, SelfTradePrevention::CancelBoth);
+        let outcome = engine.submit(sell);
+
+        assert_eq!(outcome.cancelled, 3);
+        assert!(engine.orderbook.best_price(Side::Buy).is_none());
+        assert!(engine.orderbook.best_price(Side::Sell).is_none());
+    }
+
+    #[test]
+    fn test_stp_decrement_and_cancel_emits_event() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let resting = create_stp_order("alice", Side::Sell, 50000, 8, SelfTradePrevention::None);
+        let resting_id = resting.id;
+        engine.submit(resting);
+        engine.drain_events();
+
+        let buy = create_stp_order("alice", Side::Buy, 50000, 5, SelfTradePrevention::DecrementAndCancel);
+        let outcome = engine.submit(buy);
+
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.cancelled, 5);
+        assert_eq!(engine.orderbook.levels(Side::Sell).next(), Some((Price(50000), 3)));
+
+        let events = engine.drain_events();
+        assert!(matches!(
+            events.as_slice(),
+            [EngineEvent::SelfTradePrevented {
+                mode: SelfTradePrevention::DecrementAndCancel,
+                maker_order_id,
+                taker_cancelled: 5,
+                maker_cancelled: 5,
+                ..
+            }] if *maker_order_id == resting_id
+        ));
+    }
+
+    #[test]
+    fn test_stp_fok_does_not_count_own_liquidity() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        engine.submit(create_stp_order("alice", Side::Sell, 50000, 5, SelfTradePrevention::None));
+        engine.submit(create_stp_order("bob", Side::Sell, 50000, 5, SelfTradePrevention::None));
+
+        let buy = Order {
+            time_in_force: TimeInForce::Fok,
+            ..create_stp_order("alice", Side::Buy, 50000, 10, SelfTradePrevention::CancelOldest)
+        };
+        let outcome = engine.submit(buy);
+
+        assert_eq!(outcome.rejected, Some(RejectReason::FillOrKillUnfillable));
+        assert_eq!(engine.orderbook.levels(Side::Sell).next(), Some((Price(50000), 10)));
+    }
+
+    fn btc_config() -> MarketConfig {
+        MarketConfig {
+            tick_size: 10,
+            lot_size: 5,
+            min_quantity: 5,
+            max_quantity: 1_000,
+            min_notional: 500_000,
+            price_precision: 2,
+            ..MarketConfig::new("BTC-USD", "BTC", "USD")
+        }
+    }
+
+    #[test]
+    fn test_unknown_market_is_rejected() {
+        let mut registry = MarketRegistry::new();
+
+        assert_eq!(
+            registry.submit(create_order(Side::Buy, 50000, 5)).unwrap_err(),
+            EngineError::UnknownMarket
+        );
+        assert!(registry.get_market("BTC-USD").is_none());
+    }
+
+    #[test]
+    fn test_market_config_rejections() {
+        let mut registry = funded_registry();
+        registry.register(btc_config()).unwrap();
+        assert_eq!(registry.register(btc_config()).unwrap_err(), EngineError::MarketExists);
+
+        let cases = [
+            (create_order(Side::Buy, 50005, 10), EngineError::TickViolation),
+            (create_order(Side::Buy, 50000, 12), EngineError::LotViolation),
+            (create_order(Side::Buy, 50000, 0), EngineError::InvalidQuantity),
+            (create_order(Side::Buy, 50000, 1_005), EngineError::AboveMaxQuantity),
+            (create_order(Side::Buy, 90, 5), EngineError::BelowMinNotional),
+        ];
+        for (order, reason) in cases {
+            assert_eq!(registry.submit(order).unwrap_err(), reason);
+        }
+
+        let outcome = registry.submit(create_order(Side::Buy, 50000, 10)).unwrap();
+        assert_eq!(outcome.rested, 10);
+    }
+
+    #[test]
+    fn test_post_only_slide_uses_tick_size() {
+        let mut engine = MatchingEngine::with_config(btc_config());
+
+        engine.submit(create_order(Side::Sell, 50000, 10));
+        engine.submit(create_order_tif(Side::Buy, 50000, 10, TimeInForce::PostOnlySlide));
+
+        assert_eq!(engine.orderbook.best_price(Side::Buy), Some(Price(49990)));
+    }
+
+    /// Registry whose test wallets hold plenty of every asset the tests trade
+    fn funded_registry() -> MarketRegistry {
+        let mut registry = MarketRegistry::new();
+        for wallet in ["test-wallet", "mm", "other", "other-wallet"] {
+            for asset in ["BTC", "ETH", "USD"] {
+                registry.deposit(wallet, asset, 1_000_000_000_000, 0).unwrap();
+            }
+        }
+        registry
+    }
+
+    fn btc_registry() -> MarketRegistry {
+        let mut registry = funded_registry();
+        registry.register(MarketConfig::from_symbol("BTC-USD")).unwrap();
+        registry
+    }
+
+    #[test]
+    fn test_halted_market_rejects_everything() {
+        let mut registry = btc_registry();
+
+        let order = create_order(Side::Buy, 50000, 5);
+        let order_id = order.id;
+        registry.submit(order).unwrap();
+
+        registry.set_state("BTC-USD", TradingState::Halted).unwrap();
+
+        assert_eq!(registry.submit(create_order(Side::Sell, 50000, 5)).unwrap_err(), EngineError::MarketUnavailable(TradingState::Halted));
+        assert_eq!(registry.cancel("BTC-USD", order_id, "test-wallet").unwrap_err(), EngineError::MarketUnavailable(TradingState::Halted));
+
+        registry.set_state("BTC-USD", TradingState::Continuous).unwrap();
+        let outcome = registry.submit(create_order(Side::Sell, 50000, 5)).unwrap();
+        assert_eq!(outcome.filled, 5);
+    }
+
+    #[test]
+    fn test_cancel_only_market_allows_cancels() {
+        let mut registry = btc_registry();
+
+        let order = create_order(Side::Buy, 50000, 5);
+        let order_id = order.id;
+        registry.submit(order).unwrap();
+
+        registry.set_state("BTC-USD", TradingState::CancelOnly).unwrap();
+
+        let replacement = create_order(Side::Buy, 50010, 5);
+        assert_eq!(registry.replace(replacement).unwrap_err(), EngineError::MarketUnavailable(TradingState::CancelOnly));
+        registry.cancel("BTC-USD", order_id, "test-wallet").unwrap();
+        assert!(registry.get_market("BTC-USD").unwrap().orderbook.best_price(Side::Buy).is_none());
+    }
+
+    #[test]
+    fn test_delisting_is_final_and_clears_book() {
+        let mut registry = btc_registry();
+
+        registry.submit(create_order(Side::Buy, 50000, 5)).unwrap();
+        registry.drain_events();
+
+        registry.set_state("BTC-USD", TradingState::Delisted).unwrap();
+        assert_eq!(registry.set_state("BTC-USD", TradingState::Continuous).unwrap_err(), EngineError::MarketUnavailable(TradingState::Delisted));
+        assert!(registry.get_market("BTC-USD").unwrap().orderbook.best_price(Side::Buy).is_none());
+
+        let events = registry.drain_events();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                EngineEvent::MarketStateChanged { from: TradingState::Continuous, to: TradingState::Delisted, .. },
+                EngineEvent::OrderCancelled { .. },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_trading_state_survives_snapshot() {
+        let mut registry = btc_registry();
+        registry.set_state("BTC-USD", TradingState::Halted).unwrap();
+
+        let json = serde_json::to_string(&registry).unwrap();
+        let restored: MarketRegistry = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.get_market("BTC-USD").unwrap().state, TradingState::Halted);
+    }
+
+    #[test]
+    fn test_call_period_collects_without_matching() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        engine.set_state(TradingState::PreOpen).unwrap();
+        engine.drain_events();
+
+        engine.submit(create_order(Side::Sell, 100, 10));
+        let outcome = engine.submit(create_order(Side::Buy, 101, 10));
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.rested, 10);
+
+        let events = engine.drain_events();
+        assert!(matches!(
+            events.last(),
+            Some(EngineEvent::IndicativePrice { price: Some(Price(100)), volume: 10, imbalance: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_uncross_maximises_volume() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        engine.set_state(TradingState::PreOpen).unwrap();
+
+        engine.submit(create_order(Side::Buy, 102, 10));
+        engine.submit(create_order(Side::Buy, 101, 10));
+        engine.submit(create_order(Side::Sell, 100, 5));
+        engine.submit(create_order(Side::Sell, 101, 10));
+        engine.submit(create_order(Side::Sell, 103, 10));
+
+        let trades = engine.set_state(TradingState::Continuous).unwrap();
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<u64>(), 15);
+        assert!(trades.iter().all(|t| t.price == Price(101) && t.auction_sequence == Some(1)));
+
+        // The unmatched bid and the out-of-range ask stay on the book
+        assert_eq!(engine.orderbook.levels(Side::Buy).collect::<Vec<_>>(), vec![(Price(101), 5)]);
+        assert_eq!(engine.orderbook.levels(Side::Sell).collect::<Vec<_>>(), vec![(Price(103), 10)]);
+    }
+
+    #[test]
+    fn test_uncross_minimises_imbalance() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        engine.set_state(TradingState::PreOpen).unwrap();
+
+        engine.submit(create_order(Side::Buy, 101, 10));
+        engine.submit(create_order(Side::Buy, 100, 4));
+        engine.submit(create_order(Side::Sell, 100, 10));
+
+        let uncross = engine.indicative_uncross().unwrap();
+        assert_eq!((uncross.price, uncross.volume, uncross.imbalance), (Price(101), 10, 0));
+    }
+
+    #[test]
+    fn test_uncross_uses_reference_price() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        engine.set_state(TradingState::PreOpen).unwrap();
+
+        engine.submit(create_order(Side::Buy, 102, 10));
+        engine.submit(create_order(Side::Sell, 100, 10));
+        assert_eq!(engine.indicative_uncross().unwrap().price, Price(100));
+
+        engine.last_trade_price = Some(Price(105));
+        assert_eq!(engine.indicative_uncross().unwrap().price, Price(102));
+    }
+
+    #[test]
+    fn test_call_period_rejects_immediate_orders() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        engine.set_state(TradingState::PreOpen).unwrap();
+        engine.submit(create_order(Side::Sell, 100, 10));
+
+        let market = engine.submit(create_market_order(Side::Buy, 5, OrderType::Market));
+        assert_eq!(market.rejected, Some(RejectReason::NotAllowedInAuction));
+
+        let ioc = engine.submit(create_order_tif(Side::Buy, 100, 5, TimeInForce::Ioc));
+        assert_eq!(ioc.rejected, Some(RejectReason::NotAllowedInAuction));
+        assert!(engine.orderbook.best_price(Side::Buy).is_none());
+    }
+
+    fn batch_engine(arrival: BatchArrival) -> MatchingEngine {
+        MatchingEngine::with_config(MarketConfig {
+            matching_mode: MatchingMode::FrequentBatch { interval_ms: 100, arrival },
+            ..MarketConfig::from_symbol("BTC-USD")
+        })
+    }
+
+    #[test]
+    fn test_batch_collects_until_interval_ends() {
+        let mut engine = batch_engine(BatchArrival::Randomised);
+
+        engine.submit(create_order(Side::Sell, 100, 10));
+        let outcome = engine.submit(create_order(Side::Buy, 101, 10));
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.rested, 10);
+        assert!(!engine.drain_events().iter().any(|e| matches!(e, EngineEvent::IndicativePrice { .. })));
+
+        engine.tick(100);
+        let trades: Vec<_> = engine.drain_events()
+            .into_iter()
+            .filter_map(|e| match e {
+                EngineEvent::TradeExecuted { trade, .. } => Some(trade),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].price, trades[0].quantity, trades[0].auction_sequence), (Price(100), 10, Some(1)));
+        assert!(engine.orderbook.best_price(Side::Buy).is_none() && engine.orderbook.best_price(Side::Sell).is_none());
+    }
+
+    #[test]
+    fn test_batch_clears_before_later_orders() {
+        let mut engine = batch_engine(BatchArrival::Randomised);
+
+        engine.submit(create_order(Side::Sell, 100, 10));
+        engine.submit(create_order(Side::Buy, 100, 4));
+
+        // An order stamped after the interval closes joins the next batch
+        let late = Order { timestamp: 150, ..create_order(Side::Buy, 100, 10) };
+        let late_id = late.id;
+        engine.submit(late);
+
+        assert_eq!(engine.sequence, 1);
+        assert_eq!(engine.orderbook.levels(Side::Sell).next(), Some((Price(100), 6)));
+        assert_eq!(engine.orderbook.best_order(Side::Buy).map(|o| o.id), Some(late_id));
+    }
+
+    #[test]
+    fn test_simultaneous_batch_fills_pro_rata() {
+        let mut engine = batch_engine(BatchArrival::Simultaneous);
+
+        let small = create_order(Side::Buy, 100, 10);
+        let large = create_order(Side::Buy, 100, 30);
+        let (small_id, large_id) = (small.id, large.id);
+        engine.submit(small);
+        engine.submit(large);
+        engine.submit(create_order(Side::Sell, 100, 20));
+        engine.tick(100);
+
+        let remaining: Vec<_> = engine.orderbook.orders(Side::Buy).map(|o| (o.id, o.quantity)).collect();
+        assert_eq!(remaining, vec![(small_id, 5), (large_id, 15)]);
+    }
+
+    #[test]
+    fn test_randomised_batch_is_reproducible() {
+        let run = || {
+            let mut engine = batch_engine(BatchArrival::Randomised);
+            let mut ids = vec![];
+            for i in 0..8u128 {
+                let order = Order { id: Uuid::from_u128(i + 1), ..create_order(Side::Buy, 100, 5) };
+                ids.push(order.id);
+                engine.submit(order);
+            }
+            engine.submit(create_order(Side::Sell, 100, 5));
+            engine.tick(100);
+            ids.retain(|id| engine.orderbook.orders(Side::Buy).all(|o| o.id != *id));
+            ids
+        };
+
+        let filled = run();
+        assert_eq!(filled.len(), 1);
+        assert_eq!(filled, run());
+    }
+
+    #[test]
+    fn test_batch_interval_must_be_positive() {
+        let config = MarketConfig {
+            matching_mode: MatchingMode::FrequentBatch { interval_ms: 0, arrival: BatchArrival::Simultaneous },
+            ..btc_config()
+        };
+        assert_eq!(MarketRegistry::new().register(config).unwrap_err(), EngineError::ZeroBatchInterval);
+    }
+
+    #[test]
+    fn test_cancel_mid_level_keeps_queue_order() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let ids: Vec<Uuid> = (0..5)
+            .map(|_| {
+                let order = create_order(Side::Sell, 50000, 1);
+                let id = order.id;
+                engine.submit(order);
+                id
+            })
+            .collect();
+
+        engine.cancel(ids[2], "test-wallet");
+        engine.cancel(ids[0], "test-wallet");
+        engine.cancel(ids[4], "test-wallet");
+        engine.submit(create_order(Side::Sell, 50000, 1));
+
+        let queue: Vec<Uuid> = engine.orderbook.orders(Side::Sell).map(|o| o.id).collect();
+        assert_eq!(&queue[..2], &[ids[1], ids[3]]);
+        assert_eq!(engine.orderbook.len(), 3);
+
+        let trades = engine.submit(create_order(Side::Buy, 50000, 2)).trades;
+        assert_eq!(trades.iter().map(|t| t.sell_order).collect::<Vec<_>>(), vec![ids[1], ids[3]]);
+    }
+
+    #[test]
+    fn test_empty_level_removed_on_cancel() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let order = create_order(Side::Buy, 49990, 5);
+        let order_id = order.id;
+        engine.submit(order);
+        engine.submit(create_order(Side::Buy, 49980, 5));
+
+        engine.cancel(order_id, "test-wallet");
+        assert_eq!(engine.orderbook.levels(Side::Buy).collect::<Vec<_>>(), vec![(Price(49980), 5)]);
+    }
+
+    #[test]
+    fn test_orderbook_snapshot_keeps_priority() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        for quantity in [3, 5, 7] {
+            engine.submit(create_order(Side::Sell, 50000, quantity));
+        }
+        engine.submit(create_order(Side::Buy, 49990, 4));
+        let before: Vec<Uuid> = engine.orderbook.orders(Side::Sell).map(|o| o.id).collect();
+
+        let json = serde_json::to_string(&engine).unwrap();
+        let restored: MatchingEngine = serde_json::from_str(&json).unwrap();
+
+        let after: Vec<Uuid> = restored.orderbook.orders(Side::Sell).map(|o| o.id).collect();
+        assert_eq!(before, after);
+        assert_eq!(restored.orderbook.best_price(Side::Buy), Some(Price(49990)));
+    }
+
+    #[test]
+    fn test_cancel_reports_filled_orders() {
+        let mut registry = btc_registry();
+
+        let sell = create_order(Side::Sell, 50000, 10);
+        let sell_id = sell.id;
+        registry.submit(sell).unwrap();
+        let buy = create_order(Side::Buy, 50000, 10);
+        let buy_id = buy.id;
+        registry.submit(buy).unwrap();
+
+        assert!(registry.get_market("BTC-USD").unwrap().orderbook.is_empty());
+        assert_eq!(registry.cancel("BTC-USD", sell_id, "test-wallet").unwrap(), CancelResult::AlreadyClosed(Closed::Filled));
+        assert_eq!(registry.cancel("BTC-USD", buy_id, "test-wallet").unwrap(), CancelResult::AlreadyClosed(Closed::Filled));
+    }
+
+    #[test]
+    fn test_cancel_reports_missing_and_cancelled_orders() {
+        let mut registry = btc_registry();
+
+        let order = create_order(Side::Buy, 50000, 5);
+        let order_id = order.id;
+        registry.submit(order).unwrap();
+
+        assert_eq!(registry.cancel("BTC-USD", order_id, "test-wallet").unwrap(), CancelResult::Cancelled);
+        assert_eq!(registry.cancel("BTC-USD", order_id, "test-wallet").unwrap(), CancelResult::AlreadyClosed(Closed::Cancelled));
+        assert_eq!(registry.cancel("BTC-USD", Uuid::new_v4(), "test-wallet").unwrap(), CancelResult::NotFound);
+        assert_eq!(CancelResult::NotFound.error(), Some(EngineError::OrderNotFound));
+    }
+
+    fn amendment(order: &Order, price: Option<u64>, quantity: Option<u64>) -> Amendment {
+        Amendment {
+            order_id: order.id,
+            market: order.market.clone(),
+            wallet: order.wallet.clone(),
+            side: order.side,
+            price: price.map(Price),
+            quantity,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_amend_decrease_keeps_priority() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let first = create_order(Side::Sell, 50000, 10);
+        engine.submit(first.clone());
+        engine.submit(create_order(Side::Sell, 50000, 10));
+
+        let amended = engine.amend(amendment(&first, None, Some(4)));
+        assert!(amended.priority_kept);
+        assert_eq!(amended.outcome.rested, 4);
+
+        let trades = engine.submit(create_order(Side::Buy, 50000, 4)).trades;
+        assert_eq!(trades[0].sell_order, first.id);
+        assert_eq!(trades[0].quantity, 4);
+    }
+
+    #[test]
+    fn test_amend_increase_or_reprice_loses_priority() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let first = create_order(Side::Sell, 50000, 10);
+        let second = create_order(Side::Sell, 50000, 10);
+        engine.submit(first.clone());
+        engine.submit(second.clone());
+
+        let amended = engine.amend(amendment(&first, None, Some(15)));
+        assert!(!amended.priority_kept);
+        let queue: Vec<Uuid> = engine.orderbook.orders(Side::Sell).map(|o| o.id).collect();
+        assert_eq!(queue, vec![second.id, first.id]);
+
+        // Repricing through the bid trades straight away
+        engine.submit(create_order(Side::Buy, 49990, 5));
+        let amended = engine.amend(amendment(&second, Some(49990), None));
+        assert!(!amended.priority_kept);
+        assert_eq!(amended.outcome.filled, 5);
+        assert_eq!(amended.outcome.rested, 5);
+    }
+
+    #[test]
+    fn test_amend_checks_order_wallet_and_side() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        let order = create_order(Side::Buy, 50000, 10);
+        engine.submit(order.clone());
+
+        let wrong_wallet = Amendment { wallet: "someone-else".to_string(), ..amendment(&order, None, Some(5)) };
+        assert_eq!(engine.amend(wrong_wallet).outcome.rejected, Some(RejectReason::WalletMismatch));
+
+        let wrong_side = Amendment { side: Side::Sell, ..amendment(&order, None, Some(5)) };
+        assert_eq!(engine.amend(wrong_side).outcome.rejected, Some(RejectReason::SideMismatch));
+
+        let unknown = amendment(&create_order(Side::Buy, 50000, 10), None, Some(5));
+        assert_eq!(engine.amend(unknown).outcome.rejected, Some(RejectReason::UnknownOrder));
+
+        assert_eq!(engine.orderbook.get(order.id).map(|o| o.quantity), Some(10));
+    }
+
+    #[test]
+    fn test_amend_follows_market_rules() {
+        let mut registry = funded_registry();
+        registry.register(btc_config()).unwrap();
+        let order = create_order(Side::Buy, 50000, 20);
+        registry.submit(order.clone()).unwrap();
+
+        assert_eq!(
+            registry.amend(amendment(&order, None, Some(7))).unwrap_err(),
+            EngineError::LotViolation
+        );
+        assert!(registry.amend(amendment(&order, None, Some(15))).unwrap().priority_kept);
+    }
+
+    #[test]
+    fn test_cancel_requires_owner() {
+        let mut registry = btc_registry();
+        let order = create_order(Side::Buy, 50000, 5);
+        registry.submit(order.clone()).unwrap();
+
+        assert_eq!(registry.cancel("BTC-USD", order.id, "intruder").unwrap(), CancelResult::NotOwner);
+        assert!(registry.get_market("BTC-USD").unwrap().orderbook.contains(order.id));
+        assert_eq!(registry.cancel("BTC-USD", order.id, "test-wallet").unwrap(), CancelResult::Cancelled);
+    }
+
+    #[test]
+    fn test_replace_requires_owner() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        let order = create_order(Side::Buy, 50000, 5);
+        engine.submit(order.clone());
+
+        let hijack = Order { wallet: "intruder".to_string(), price: Price(49000), ..order.clone() };
+        assert_eq!(engine.replace(hijack).unwrap_err(), EngineError::NotOwner);
+        assert_eq!(engine.owner(order.id), Some("test-wallet"));
+        assert_eq!(engine.orderbook.get(order.id).map(|o| o.price), Some(Price(50000)));
+    }
+
+    #[test]
+    fn test_replace_requires_live_order() {
+        let mut registry = btc_registry();
+        let order = create_order(Side::Buy, 50000, 5);
+        registry.submit(order.clone()).unwrap();
+        registry.submit(create_order(Side::Sell, 50000, 5)).unwrap();
+
+        let hijack = Order { wallet: "intruder".to_string(), ..create_order(Side::Buy, 49000, 5) };
+        let hijack = Order { id: order.id, ..hijack };
+        assert_eq!(registry.replace(hijack).unwrap_err(), EngineError::NotOwner);
+        let revive = Order { id: order.id, ..create_order(Side::Buy, 49000, 5) };
+        assert_eq!(registry.replace(revive).unwrap_err(), EngineError::OrderClosed(Closed::Filled));
+        let report = registry.order("BTC-USD", order.id).unwrap().unwrap();
+        assert_eq!((report.record.wallet.as_str(), report.record.status), ("test-wallet", OrderStatus::Filled));
+
+        let unused = create_order(Side::Buy, 49000, 5);
+        assert_eq!(registry.replace(unused.clone()).unwrap_err(), EngineError::OrderNotFound);
+        assert!(registry.order("BTC-USD", unused.id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_mass_cancel_by_side() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+
+        let bid = create_order(Side::Buy, 49990, 5);
+        let ask = create_order(Side::Sell, 50010, 5);
+        let stop = create_market_order(Side::Buy, 5, OrderType::Stop { trigger: Price(51000) });
+        let other = Order { wallet: "other-wallet".to_string(), ..create_order(Side::Buy, 49980, 5) };
+        for order in [bid.clone(), ask.clone(), stop.clone(), other.clone()] {
+            engine.submit(order);
+        }
+        engine.drain_events();
+
+        let mut cancelled = engine.mass_cancel("test-wallet", Some(Side::Buy));
+        cancelled.sort();
+        let mut expected = vec![bid.id, stop.id];
+        expected.sort();
+        assert_eq!(cancelled, expected);
+
+        let events = engine.drain_events();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| matches!(e, EngineEvent::OrderCancelled { .. })));
+        assert!(engine.orderbook.contains(ask.id) && engine.orderbook.contains(other.id));
+    }
+
+    #[test]
+    fn test_mass_cancel_across_markets() {
+        let mut registry = btc_registry();
+        registry.register(MarketConfig::from_symbol("ETH-USD")).unwrap();
+
+        let btc = create_order(Side::Buy, 50000, 5);
+        let eth = Order { market: "ETH-USD".to_string(), ..create_order(Side::Sell, 3000, 5) };
+        registry.submit(btc.clone()).unwrap();
+        registry.submit(eth.clone()).unwrap();
+
+        assert_eq!(registry.mass_cancel("test-wallet", Some("ETH-USD"), None).unwrap(), vec![eth.id]);
+        assert_eq!(registry.mass_cancel("test-wallet", None, None).unwrap(), vec![btc.id]);
+        assert!(registry.mass_cancel("test-wallet", None, None).unwrap().is_empty());
+        assert_eq!(registry.mass_cancel("test-wallet", Some("SOL-USD"), None).unwrap_err(), EngineError::UnknownMarket);
+    }
+
+    #[test]
+    fn test_session_orders_expire_after_last_disconnect() {
+        let sessions = Sessions::new();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        sessions.connect("test-wallet");
+        sessions.connect("test-wallet");
+        sessions.track("test-wallet", "BTC-USD", first);
+        sessions.track("test-wallet", "BTC-USD", second);
+
+        // Another session is still live
+        let generation = sessions.disconnect("test-wallet");
+        assert!(sessions.expire("test-wallet", generation).is_empty());
+
+        // Reconnecting within the grace period adopts the orders
+        let generation = sessions.disconnect("test-wallet");
+        sessions.connect("test-wallet");
+        assert!(sessions.expire("test-wallet", generation).is_empty());
+
+        sessions.retain("test-wallet", |_, order_id| order_id != first);
+        let generation = sessions.disconnect("test-wallet");
+        assert_eq!(sessions.expire("test-wallet", generation), vec![("BTC-USD".to_string(), second)]);
+        assert!(sessions.expire("test-wallet", generation).is_empty());
+    }
+
+    fn quote(side: Side, price: u64, quantity: u64) -> Order {
+        Order { wallet: "mm".to_string(), ..create_order(side, price, quantity) }
+    }
+
+    #[test]
+    fn test_mmp_pulls_quotes_after_fill_count() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        engine.set_mmp("mm", Some(MmpLimits { window_ms: 1000, quantity: 0, delta: 0, fills: 2 }));
+
+        let asks: Vec<Order> = (0..3).map(|_| quote(Side::Sell, 50000, 5)).collect();
+        for ask in &asks {
+            engine.submit(ask.clone());
+        }
+        let bid = quote(Side::Buy, 49000, 5);
+        engine.submit(bid.clone());
+        engine.drain_events();
+
+        // The second fill trips protection before the third ask can be hit
+        let outcome = engine.submit(create_order(Side::Buy, 50000, 15));
+        assert_eq!(outcome.filled, 10);
+        assert_eq!(outcome.rested, 5);
+        assert!(engine.orderbook.wallet_orders("mm").next().is_none());
+        assert!(engine.drain_events().iter().any(|e| matches!(
+            e,
+            EngineEvent::MmpTriggered { cancelled_orders, .. } if cancelled_orders.len() == 2
+        )));
+
+        // New quotes are blocked, hedges are not
+        let blocked = engine.submit(quote(Side::Sell, 51000, 5));
+        assert_eq!(blocked.rejected, Some(RejectReason::MmpTriggered));
+        let hedge = engine.submit(Order { wallet: "mm".to_string(), ..create_order_tif(Side::Sell, 50000, 5, TimeInForce::Ioc) });
+        assert_eq!(hedge.filled, 5);
+
+        assert!(engine.reset_mmp("mm"));
+        assert!(engine.submit(quote(Side::Sell, 51000, 5)).rejected.is_none());
+    }
+
+    #[test]
+    fn test_mmp_counts_within_rolling_window() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        engine.set_mmp("mm", Some(MmpLimits { window_ms: 1000, quantity: 0, delta: 10, fills: 0 }));
+
+        for _ in 0..4 {
+            engine.submit(quote(Side::Sell, 50000, 5));
+        }
+        engine.submit(quote(Side::Buy, 49000, 20));
+
+        let at = |time, order: Order| Order { timestamp: time, ..order };
+        engine.submit(at(100, create_order(Side::Buy, 50000, 5)));
+        // Buying back offsets the delta
+        engine.submit(at(200, create_order(Side::Sell, 49000, 5)));
+        engine.submit(at(300, create_order(Side::Buy, 50000, 5)));
+        // The first fill has left the window by now
+        engine.submit(at(1100, create_order(Side::Buy, 50000, 5)));
+        assert!(!engine.mmp("mm").unwrap().triggered);
+
+        engine.submit(at(1200, create_order(Side::Buy, 50000, 5)));
+        assert!(engine.mmp("mm").unwrap().triggered);
+        assert!(engine.orderbook.wallet_orders("mm").next().is_none());
+    }
+
+    #[test]
+    fn test_mmp_limits_are_validated() {
+        let mut registry = btc_registry();
+        let none = MmpLimits { window_ms: 1000, quantity: 0, delta: 0, fills: 0 };
+        assert_eq!(registry.set_mmp("BTC-USD", "mm", Some(none)).unwrap_err(), EngineError::MissingMmpLimits);
+        let no_window = MmpLimits { window_ms: 0, fills: 3, ..none };
+        assert_eq!(registry.set_mmp("BTC-USD", "mm", Some(no_window)).unwrap_err(), EngineError::InvalidMmpWindow);
+        assert_eq!(registry.reset_mmp("BTC-USD", "mm").unwrap_err(), EngineError::MmpNotConfigured);
+    }
+
+    #[test]
+    fn test_order_lifecycle_and_average_price() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        let asks = [create_order(Side::Sell, 50000, 5), create_order(Side::Sell, 50100, 5)];
+        for ask in &asks {
+            engine.submit(ask.clone());
+        }
+        assert_eq!(engine.report(asks[0].id).unwrap().record.status, OrderStatus::New);
+
+        let bid = create_order(Side::Buy, 50100, 20);
+        engine.submit(bid.clone());
+        let report = engine.report(bid.id).unwrap();
+        assert_eq!(report.record.status, OrderStatus::PartiallyFilled);
+        assert_eq!(report.record.filled, 10);
+        assert_eq!(report.record.average_price(), Some(Price(50050)));
+        assert_eq!(report.open_quantity, 10);
+        assert_eq!(engine.report(asks[1].id).unwrap().record.status, OrderStatus::Filled);
+
+        // Cancelling after a fill keeps the fills on record
+        engine.cancel(bid.id, "test-wallet");
+        let report = engine.report(bid.id).unwrap();
+        assert_eq!(report.record.status, OrderStatus::Cancelled);
+        assert_eq!((report.record.filled, report.open_quantity), (10, 0));
+
+        let post_only = create_order_tif(Side::Buy, 50000, 5, TimeInForce::PostOnly);
+        engine.submit(create_order(Side::Sell, 50000, 5));
+        engine.submit(post_only.clone());
+        assert_eq!(engine.report(post_only.id).unwrap().record.status, OrderStatus::Rejected);
+
+        let gtd = create_order_tif(Side::Buy, 40000, 5, TimeInForce::Gtd { expires_at: 1000 });
+        engine.submit(gtd.clone());
+        engine.tick(1000);
+        assert_eq!(engine.report(gtd.id).unwrap().record.status, OrderStatus::Expired);
+        assert!(engine.report(Uuid::new_v4()).is_none());
+    }
+
+    #[test]
+    fn test_open_orders_by_wallet() {
+        let mut registry = btc_registry();
+        registry.register(MarketConfig::from_symbol("ETH-USD")).unwrap();
+
+        let bid = create_order(Side::Buy, 50000, 5);
+        let stop = create_market_order(Side::Sell, 5, OrderType::Stop { trigger: Price(49000) });
+        let eth = Order { market: "ETH-USD".to_string(), ..create_order(Side::Sell, 3000, 5) };
+        let filled = create_order(Side::Sell, 51000, 5);
+        for order in [bid.clone(), stop.clone(), eth.clone(), filled.clone()] {
+            registry.submit(order).unwrap();
+        }
+        registry.submit(Order { wallet: "other".to_string(), ..create_order(Side::Buy, 51000, 5) }).unwrap();
+
+        let ids = |reports: Vec<OrderReport>| reports.into_iter().map(|r| r.order_id).collect::<Vec<_>>();
+        let btc = ids(registry.open_orders("test-wallet", Some("BTC-USD")).unwrap());
+        assert_eq!(btc.len(), 2);
+        assert!(btc.contains(&bid.id) && btc.contains(&stop.id));
+        assert_eq!(ids(registry.open_orders("test-wallet", None).unwrap()).len(), 3);
+        assert!(registry.open_orders("other", None).unwrap().is_empty());
+        assert_eq!(registry.order("BTC-USD", filled.id).unwrap().unwrap().record.status, OrderStatus::Filled);
+    }
+
+    fn with_client_id(order: Order, client_order_id: &str) -> Order {
+        Order { client_order_id: Some(client_order_id.to_string()), ..order }
+    }
+
+    #[test]
+    fn test_client_order_id_resubmission_is_idempotent() {
+        let mut registry = btc_registry();
+        let order = with_client_id(create_order(Side::Buy, 50000, 5), "quote-1");
+        let first = registry.submit(order.clone()).unwrap();
+
+        // A retry mints a new id but gets the original result back
+        let retry = registry.submit(Order { id: Uuid::new_v4(), ..order.clone() }).unwrap();
+        assert_eq!(retry.order_id, order.id);
+        assert_eq!(retry.rested, first.rested);
+        assert_eq!(registry.open_orders("test-wallet", None).unwrap().len(), 1);
+
+        let different = with_client_id(create_order(Side::Buy, 50100, 5), "quote-1");
+        assert_eq!(registry.submit(different).unwrap_err(), EngineError::DuplicateClientOrderId);
+
+        // Uniqueness is per wallet
+        let other = Order { wallet: "other".to_string(), ..with_client_id(create_order(Side::Buy, 50000, 5), "quote-1") };
+        assert!(registry.submit(other).unwrap().rejected.is_none());
+    }
+
+    #[test]
+    fn test_client_order_ids_survive_snapshot_until_window_ends() {
+        let mut registry = btc_registry();
+        let order = with_client_id(create_order(Side::Sell, 50000, 5), "quote-1");
+        registry.submit(order.clone()).unwrap();
+
+        let snapshot = serde_json::to_string(&registry).unwrap();
+        let mut registry: MarketRegistry = serde_json::from_str(&snapshot).unwrap();
+        let retry = registry.submit(Order { id: Uuid::new_v4(), ..order.clone() }).unwrap();
+        assert_eq!(retry.order_id, order.id);
+
+        let later = Order { id: Uuid::new_v4(), timestamp: CLIENT_ORDER_ID_WINDOW_MS, ..order.clone() };
+        let outcome = registry.submit(later.clone()).unwrap();
+        assert_eq!(outcome.order_id, later.id);
+        assert!(outcome.rejected.is_none());
+    }
+
+    #[test]
+    fn test_order_ids_cannot_be_reused() {
+        let mut registry = btc_registry();
+        let order = create_order(Side::Buy, 50000, 5);
+        registry.submit(order.clone()).unwrap();
+        registry.cancel("BTC-USD", order.id, "test-wallet").unwrap();
+
+        let error = registry.submit(order).unwrap_err();
+        assert_eq!(error, EngineError::DuplicateOrderId);
+        assert_eq!(error.code(), "DUPLICATE_ORDER_ID");
+        assert_eq!(EngineError::MarketUnavailable(TradingState::Halted).code(), "MARKET_HALTED");
+        assert_eq!(EngineError::MarketUnavailable(TradingState::Halted).to_string(), "Market is halted");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_proto_order_side_is_parsed_strictly() {
+        use crate::api::grpc::{engine_proto, parse_order};
+        let order = |side: &str, side_enum: engine_proto::Side| engine_proto::Order {
+            market: "BTC-USD".to_string(),
+            side: side.to_string(),
+            side_enum: side_enum.into(),
+            price: 50000,
+            quantity: 5,
+            ..Default::default()
+        };
+
+        assert_eq!(parse_order(order("", engine_proto::Side::Buy)).unwrap().side, Side::Buy);
+        assert_eq!(parse_order(order("SELL", engine_proto::Side::Unspecified)).unwrap().side, Side::Sell);
+        assert_eq!(parse_order(order("BUY", engine_proto::Side::Buy)).unwrap().side, Side::Buy);
+
+        for (side, side_enum) in [
+            ("", engine_proto::Side::Unspecified),
+            ("buy", engine_proto::Side::Unspecified),
+            ("SELL", engine_proto::Side::Buy),
+        ] {
+            let status = parse_order(order(side, side_enum)).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+
+        let typed = engine_proto::Order {
+            order_type_enum: engine_proto::OrderType::Market.into(),
+            time_in_force_enum: engine_proto::TimeInForce::Ioc.into(),
+            ..order("", engine_proto::Side::Buy)
+        };
+        let parsed = parse_order(typed).unwrap();
+        assert_eq!((parsed.order_type, parsed.time_in_force), (OrderType::Market, TimeInForce::Ioc));
+
+        let unknown = engine_proto::Order { time_in_force_enum: 42, ..order("", engine_proto::Side::Buy) };
+        assert!(parse_order(unknown).is_err());
+    }
+
+    #[test]
+    fn test_trade_names_maker_and_taker() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        engine.tick(500);
+        let maker = quote(Side::Sell, 100, 10);
+        let taker = create_order(Side::Buy, 100, 4);
+        let (maker_id, taker_id) = (maker.id, taker.id);
+        engine.submit(maker);
+
+        let trade = engine.submit(taker).trades.remove(0);
+        assert_eq!(trade.taker_side, Some(Side::Buy));
+        assert_eq!((trade.buy_order, trade.sell_order), (taker_id, maker_id));
+        assert_eq!((trade.maker_order, trade.taker_order), (maker_id, taker_id));
+        assert_eq!((trade.maker_wallet.as_str(), trade.taker_wallet.as_str()), ("mm", "test-wallet"));
+        assert_eq!(trade.executed_at, 500);
+
+        // An uncross has no aggressor; the buy order is reported as maker
+        engine.set_state(TradingState::PreOpen).unwrap();
+        let bid = create_order(Side::Buy, 100, 6);
+        let bid_id = bid.id;
+        engine.submit(bid);
+        engine.tick(900);
+
+        let trade = engine.set_state(TradingState::Continuous).unwrap().remove(0);
+        assert_eq!(trade.taker_side, None);
+        assert_eq!((trade.maker_order, trade.taker_order), (bid_id, maker_id));
+        assert_eq!((trade.maker_wallet.as_str(), trade.taker_wallet.as_str()), ("test-wallet", "mm"));
+        assert_eq!(trade.executed_at, 900);
+    }
+
+    fn fee_engine() -> MatchingEngine {
+        MatchingEngine::with_config(MarketConfig {
+            fees: FeeSchedule {
+                base: FeeRates { maker_bps: -1, taker_bps: 5 },
+                tiers: vec![FeeTier { min_volume: 150_000, rates: FeeRates { maker_bps: -2, taker_bps: 3 } }],
+            },
+            ..MarketConfig::from_symbol("BTC-USD")
+        })
+    }
+
+    #[test]
+    fn test_trades_carry_fees_by_volume_tier() {
+        let mut engine = fee_engine();
+        let mut trade = || {
+            engine.submit(quote(Side::Sell, 10_000, 10));
+            engine.submit(create_order(Side::Buy, 10_000, 10)).trades.remove(0)
+        };
+
+        let first = trade();
+        assert_eq!((first.maker_fee, first.taker_fee, first.fee_currency.as_str()), (-10, 50, "USD"));
+        // Tiers go by volume before the trade, so the second is still at base rates
+        let second = trade();
+        assert_eq!((second.maker_fee, second.taker_fee), (-10, 50));
+        let third = trade();
+        assert_eq!((third.maker_fee, third.taker_fee), (-20, 30));
+
+        let json = serde_json::to_string(&engine).unwrap();
+        let mut restored: MatchingEngine = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.fee_rates("mm"), FeeRates { maker_bps: -2, taker_bps: 3 });
+
+        restored.tick(FEE_VOLUME_WINDOW_DAYS * 24 * 60 * 60 * 1000);
+        assert_eq!(restored.fee_rates("mm"), FeeRates { maker_bps: -1, taker_bps: 5 });
+    }
+
+    #[test]
+    fn test_fees_round_in_exchange_favour() {
+        assert_eq!(fees::fee(10_001, 5), 6);
+        assert_eq!(fees::fee(10_001, -1), -1);
+        assert_eq!(fees::fee(9_999, -1), 0);
+    }
+
+    #[test]
+    fn test_auction_trades_charge_taker_rates() {
+        let mut engine = fee_engine();
+        engine.set_state(TradingState::PreOpen).unwrap();
+        engine.submit(quote(Side::Buy, 10_000, 10));
+        engine.submit(create_order(Side::Sell, 10_000, 10));
+
+        let trade = engine.set_state(TradingState::Continuous).unwrap().remove(0);
+        assert_eq!((trade.maker_fee, trade.taker_fee), (50, 50));
+    }
+
+    #[test]
+    fn test_fee_schedule_is_checked() {
+        let config = |fees| MarketConfig { fees, ..MarketConfig::from_symbol("BTC-USD") };
+        let rebate_above_fee = FeeSchedule { base: FeeRates { maker_bps: -6, taker_bps: 5 }, tiers: vec![] };
+        assert_eq!(config(rebate_above_fee).check(), Err(EngineError::InvalidFeeRate));
+
+        // A top-tier maker can trade against a base-tier taker paying less
+        let rebate_above_other_tier = FeeSchedule {
+            base: FeeRates { maker_bps: 2, taker_bps: 3 },
+            tiers: vec![FeeTier { min_volume: 1_000, rates: FeeRates { maker_bps: -4, taker_bps: 5 } }],
+        };
+        assert_eq!(config(rebate_above_other_tier).check(), Err(EngineError::InvalidFeeRate));
+
+        let tier = |min_volume| FeeTier { min_volume, rates: FeeRates::default() };
+        let unsorted = FeeSchedule { base: FeeRates::default(), tiers: vec![tier(10), tier(10)] };
+        assert_eq!(config(unsorted).check(), Err(EngineError::UnsortedFeeTiers));
+    }
+
+    fn ledger_registry(fees: FeeSchedule) -> MarketRegistry {
+        let mut registry = MarketRegistry::new();
+        registry.register(MarketConfig { fees, ..MarketConfig::from_symbol("BTC-USD") }).unwrap();
+        registry.deposit("buyer", "USD", 10_000, 0).unwrap();
+        registry.deposit("seller", "BTC", 50, 0).unwrap();
+        registry
+    }
+
+    fn balance(registry: &MarketRegistry, wallet: &str, asset: &str) -> (u128, u128) {
+        let Balance { available, locked } = registry.ledger().balance(wallet, asset);
+        (available, locked)
+    }
+
+    #[test]
+    fn test_orders_lock_funds_until_they_leave_the_book() {
+        let mut registry = ledger_registry(FeeSchedule::default());
+        let bid = Order { wallet: "buyer".to_string(), ..create_order(Side::Buy, 100, 30) };
+        registry.submit(bid.clone()).unwrap();
+        assert_eq!(balance(&registry, "buyer", "USD"), (7_000, 3_000));
+
+        let too_big = Order { wallet: "buyer".to_string(), ..create_order(Side::Buy, 100, 71) };
+        assert_eq!(registry.submit(too_big.clone()).unwrap_err(), EngineError::InsufficientBalance);
+        assert!(registry.order("BTC-USD", too_big.id).unwrap().is_none());
+
+        registry.cancel("BTC-USD", bid.id, "buyer").unwrap();
+        assert_eq!(balance(&registry, "buyer", "USD"), (10_000, 0));
+
+        // The ask trades at its own price; the bid's price improvement is released
+        let ask = Order { wallet: "seller".to_string(), ..create_order(Side::Sell, 90, 20) };
+        registry.submit(ask).unwrap();
+        assert_eq!(balance(&registry, "seller", "BTC"), (30, 20));
+        let bid = Order { wallet: "buyer".to_string(), ..create_order(Side::Buy, 100, 30) };
+        registry.submit(bid).unwrap();
+
+        assert_eq!(balance(&registry, "buyer", "USD"), (7_000 + 10 * 20, 1_000));
+        assert_eq!(balance(&registry, "buyer", "BTC"), (20, 0));
+        assert_eq!(balance(&registry, "seller", "BTC"), (30, 0));
+        assert_eq!(balance(&registry, "seller", "USD"), (1_800, 0));
+    }
+
+    #[test]
+    fn test_trades_settle_fees_through_the_ledger() {
+        let fees = FeeSchedule { base: FeeRates { maker_bps: -10, taker_bps: 30 }, tiers: vec![] };
+        let mut registry = ledger_registry(fees);
+
+        let ask = Order { wallet: "seller".to_string(), ..create_order(Side::Sell, 100, 50) };
+        registry.submit(ask).unwrap();
+        // The bid holds 100 plus a 0.3 fee, rounded up to 1, per unit
+        let bid = Order { wallet: "buyer".to_string(), ..create_order(Side::Buy, 100, 100) };
+        assert_eq!(registry.submit(bid).unwrap_err(), EngineError::InsufficientBalance);
+        let bid = Order { wallet: "buyer".to_string(), ..create_order(Side::Buy, 100, 50) };
+        registry.submit(bid).unwrap();
+
+        // Notional 5000: the taker pays 15 on top, the maker earns a 5 rebate
+        assert_eq!(balance(&registry, "buyer", "USD"), (10_000 - 5_015, 0));
+        assert_eq!(balance(&registry, "seller", "USD"), (5_005, 0));
+        assert_eq!(registry.ledger().fees("USD"), 10);
+    }
+
+    #[test]
+    fn test_market_buys_hold_the_worst_price_they_can_reach() {
+        let mut registry = ledger_registry(FeeSchedule::default());
+        registry.submit(Order { wallet: "seller".to_string(), ..create_order(Side::Sell, 100, 30) }).unwrap();
+        registry.submit(Order { wallet: "seller".to_string(), ..create_order(Side::Sell, 200, 20) }).unwrap();
+
+        let market = |qty| Order { wallet: "buyer".to_string(), ..create_market_order(Side::Buy, qty, OrderType::Market) };
+        assert_eq!(registry.submit(market(60)).unwrap_err(), EngineError::InsufficientBalance);
+        let outcome = registry.submit(market(40)).unwrap();
+        assert_eq!(outcome.filled, 40);
+        assert_eq!(balance(&registry, "buyer", "USD"), (10_000 - 3_000 - 2_000, 0));
+
+        let stop = Order {
+            wallet: "buyer".to_string(),
+            ..create_market_order(Side::Buy, 5, OrderType::Stop { trigger: Price(300) })
+        };
+        assert_eq!(registry.submit(stop).unwrap_err(), EngineError::UnpricedStopBuy);
+    }
+
+    #[test]
+    fn test_stop_buys_hold_and_respect_their_price_cap() {
+        let mut registry = ledger_registry(FeeSchedule::default());
+        for (price, qty) in [(100, 5), (300, 10), (400, 5)] {
+            registry.submit(Order { wallet: "seller".to_string(), ..create_order(Side::Sell, price, qty) }).unwrap();
+        }
+
+        let stop = Order {
+            wallet: "buyer".to_string(),
+            price: Price(300),
+            ..create_market_order(Side::Buy, 10, OrderType::Stop { trigger: Price(300) })
+        };
+        assert_eq!(registry.submit(stop.clone()).unwrap().parked, 10);
+        assert_eq!(balance(&registry, "buyer", "USD"), (7_000, 3_000));
+
+        // Lifting the 300 offer triggers the stop, which takes the rest of it
+        // but not the 400 offer above its cap
+        let outcome = registry.submit(Order { wallet: "buyer".to_string(), ..create_order(Side::Buy, 300, 10) }).unwrap();
+        assert_eq!(outcome.triggered, vec![stop.id]);
+        let stop_fills: Vec<(u64, u64)> = outcome.trades.iter()
+            .filter(|t| t.buy_order == stop.id)
+            .map(|t| (t.price.0, t.quantity))
+            .collect();
+        assert_eq!(stop_fills, vec![(300, 5)]);
+        assert_eq!(balance(&registry, "buyer", "USD"), (10_000 - 500 - 1_500 - 1_500, 0));
+        assert_eq!(balance(&registry, "buyer", "BTC"), (15, 0));
+        assert_eq!(registry.get_market("BTC-USD").unwrap().orderbook.best_price(Side::Sell), Some(Price(400)));
+    }
+
+    #[test]
+    fn test_deposits_and_withdrawals_are_journaled() {
+        let mut registry = ledger_registry(FeeSchedule::default());
+        registry.submit(Order { wallet: "seller".to_string(), ..create_order(Side::Sell, 100, 20) }).unwrap();
+
+        assert_eq!(registry.withdraw("seller", "BTC", 31, 0).unwrap_err(), EngineError::InsufficientBalance);
+        assert_eq!(registry.withdraw("seller", "BTC", 30, 7_000).unwrap(), Balance { available: 0, locked: 20 });
+        assert_eq!(registry.deposit("seller", "BTC", 0, 0).unwrap_err(), EngineError::InvalidAmount);
+
+        let journal: Vec<_> = registry.ledger()
+            .journal()
+            .iter()
+            .map(|entry| (entry.sequence, entry.wallet.as_str(), entry.kind, entry.amount, entry.available, entry.timestamp))
+            .collect();
+        assert_eq!(journal, vec![
+            (1, "buyer", TransferKind::Deposit, 10_000, 10_000, 0),
+            (2, "seller", TransferKind::Deposit, 50, 50, 0),
+            (3, "seller", TransferKind::Withdrawal, 30, 0, 7_000),
+        ]);
+
+        let snapshot = serde_json::to_string(&registry).unwrap();
+        let restored: MarketRegistry = serde_json::from_str(&snapshot).unwrap();
+        assert_eq!(balance(&restored, "seller", "BTC"), (0, 20));
+        assert_eq!(restored.ledger().journal().len(), 3);
+    }
+
+    fn risk_registry(risk: RiskLimits) -> MarketRegistry {
+        let mut registry = funded_registry();
+        registry.register(MarketConfig { risk, ..MarketConfig::from_symbol("BTC-USD") }).unwrap();
+        registry
+    }
+
+    #[test]
+    fn test_price_collar_follows_reference() {
+        let collar = |reference| RiskLimits {
+            price_collar: Some(PriceCollar { reference, bps: 500 }),
+            ..RiskLimits::default()
+        };
+
+        // Nothing to measure from until the market has traded
+        let mut registry = risk_registry(collar(CollarReference::LastTrade));
+        registry.submit(quote(Side::Sell, 100, 5)).unwrap();
+        registry.submit(create_order(Side::Buy, 100, 5)).unwrap();
+        assert_eq!(registry.submit(create_order(Side::Buy, 106, 5)).unwrap_err(), EngineError::PriceOutsideCollar);
+        assert_eq!(registry.submit(create_order(Side::Sell, 94, 5)).unwrap_err(), EngineError::PriceOutsideCollar);
+        registry.submit(create_order(Side::Buy, 95, 5)).unwrap();
+
+        let mut registry = risk_registry(collar(CollarReference::Mid));
+        registry.submit(create_order(Side::Buy, 190, 5)).unwrap();
+        registry.submit(create_order(Side::Sell, 210, 5)).unwrap();
+        assert_eq!(registry.submit(create_order(Side::Buy, 189, 5)).unwrap_err(), EngineError::PriceOutsideCollar);
+        registry.submit(create_order(Side::Sell, 209, 5)).unwrap();
+    }
+
+    #[test]
+    fn test_order_size_limits() {
+        let mut registry = risk_registry(RiskLimits {
+            max_quantity: Some(MaxQuantity(10)),
+            max_notional: Some(MaxNotional(1_000)),
+            ..RiskLimits::default()
+        });
+
+        assert_eq!(registry.submit(create_order(Side::Buy, 50, 11)).unwrap_err(), EngineError::OrderQuantityLimit);
+        assert_eq!(registry.submit(create_order(Side::Buy, 101, 10)).unwrap_err(), EngineError::OrderNotionalLimit);
+        registry.submit(create_order(Side::Buy, 100, 10)).unwrap();
+
+        // Price and quantity at their limits multiply without overflow
+        let mut registry = risk_registry(RiskLimits {
+            max_notional: Some(MaxNotional(u128::MAX - 1)),
+            ..RiskLimits::default()
+        });
+        let huge = create_order(Side::Sell, u64::MAX, u64::MAX);
+        assert_eq!(registry.submit(huge).unwrap_err(), EngineError::InsufficientBalance);
+    }
+
+    #[test]
+    fn test_open_order_limit_and_kill_switch() {
+        let mut registry = risk_registry(RiskLimits {
+            max_open_orders: Some(MaxOpenOrders(2)),
+            ..RiskLimits::default()
+        });
+        let first = create_order(Side::Buy, 100, 10);
+        registry.submit(first.clone()).unwrap();
+        registry.submit(create_order(Side::Buy, 99, 10)).unwrap();
+        assert_eq!(registry.submit(create_order(Side::Buy, 98, 10)).unwrap_err(), EngineError::OpenOrderLimit);
+        // Amending does not add an order
+        registry.amend(amendment(&first, None, Some(5))).unwrap();
+
+        let cancelled = registry.set_kill_switch("test-wallet", true).unwrap();
+        assert!(cancelled.len() == 2 && cancelled.contains(&first.id));
+        assert_eq!(registry.submit(create_order(Side::Buy, 98, 10)).unwrap_err(), EngineError::KillSwitchEngaged);
+        registry.submit(quote(Side::Buy, 98, 10)).unwrap();
+
+        registry.set_kill_switch("test-wallet", false).unwrap();
+        registry.submit(create_order(Side::Buy, 98, 10)).unwrap();
+    }
+
+    #[test]
+    fn test_risk_limits_must_be_positive() {
+        let risk = RiskLimits { max_open_orders: Some(MaxOpenOrders(0)), ..RiskLimits::default() };
+        let config = MarketConfig { risk, ..MarketConfig::from_symbol("BTC-USD") };
+        assert_eq!(MarketRegistry::new().register(config).unwrap_err(), EngineError::InvalidRiskLimit);
+    }
+
+    #[test]
+    fn test_replay_restores_transfers_before_orders() {
+        let mut live = MarketRegistry::new();
+        live.register(btc_config()).unwrap();
+        let path = std::env::temp_dir().join(format!("replay-{}.log", Uuid::new_v4()));
+
+        live.deposit("test-wallet", "USD", 10_000_000, 0).unwrap();
+        live.deposit("test-wallet", "BTC", 10, 0).unwrap();
+        for transfer in live.ledger().journal() {
+            replay::append_transfer_to_log(transfer, &path).unwrap();
+        }
+        let bid = create_order(Side::Buy, 50000, 10);
+        replay::append_to_log(&bid, &path).unwrap();
+        live.submit(bid.clone()).unwrap();
+        live.withdraw("test-wallet", "BTC", 4, 2_000).unwrap();
+        replay::append_transfer_to_log(live.ledger().journal().last().unwrap(), &path).unwrap();
+
+        let replayed = replay::replay_from_log(&path, vec![btc_config()]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(replayed.get_market("BTC-USD").unwrap().orderbook.contains(bid.id));
+        for asset in ["USD", "BTC"] {
+            assert_eq!(balance(&replayed, "test-wallet", asset), balance(&live, "test-wallet", asset));
+        }
+        assert_eq!(replayed.ledger().journal(), live.ledger().journal());
+    }
+
+    #[test]
+    fn test_closed_order_records_expire_after_retention() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        let resting = create_order(Side::Buy, 49000, 5);
+        let cancelled = create_order(Side::Buy, 49500, 5);
+        engine.submit(resting.clone());
+        engine.submit(cancelled.clone());
+        engine.tick(1_000);
+        assert_eq!(engine.cancel(cancelled.id, "test-wallet"), CancelResult::Cancelled);
+
+        engine.tick(1_000 + ORDER_RECORD_RETENTION_MS - 1);
+        assert_eq!(engine.report(cancelled.id).unwrap().record.status, OrderStatus::Cancelled);
+
+        engine.tick(1_000 + ORDER_RECORD_RETENTION_MS);
+        assert!(engine.report(cancelled.id).is_none());
+        assert_eq!(engine.cancel(cancelled.id, "test-wallet"), CancelResult::NotFound);
+        // Live orders keep their record however old they are
+        assert_eq!(engine.report(resting.id).unwrap().record.status, OrderStatus::New);
+    }
+
+    /// Serve `engine` on a local port, returning a client connected to it
+    async fn serve(engine: GrpcEngine) -> MatchingEngineClient<tonic::transport::Channel> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(tonic::transport::Server::builder()
+            .add_service(MatchingEngineServer::new(engine))
+            .serve_with_incoming(incoming));
+        MatchingEngineClient::connect(format!("http://{addr}")).await.unwrap()
+    }
+
+    fn session_engine() -> GrpcEngine {
+        GrpcEngine {
+            registry: std::sync::Arc::new(tokio::sync::Mutex::new(btc_registry())),
+            session_config: SessionConfig {
+                heartbeat_timeout: std::time::Duration::from_millis(300),
+                grace_period: std::time::Duration::from_millis(200),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Open an order session and log on as `wallet`, returning the request
+    /// sender, which closes the session when dropped, and the responses
+    async fn open_session(
+        client: &mut MatchingEngineClient<tonic::transport::Channel>,
+        first: session_request::Request,
+    ) -> (tokio::sync::mpsc::Sender<SessionRequest>, tonic::Streaming<SessionResponse>) {
+        let (requests, stream) = tokio::sync::mpsc::channel(8);
+        requests.send(SessionRequest { request: Some(first) }).await.unwrap();
+        let responses = client
+            .order_session(tokio_stream::wrappers::ReceiverStream::new(stream))
+            .await
+            .unwrap()
+            .into_inner();
+        (requests, responses)
+    }
+
+    fn logon(wallet: &str) -> session_request::Request {
+        session_request::Request::Logon(engine_proto::Logon { wallet: wallet.to_string() })
+    }
+
+    async fn heartbeat(requests: &tokio::sync::mpsc::Sender<SessionRequest>, responses: &mut tonic::Streaming<SessionResponse>) {
+        let heartbeat = session_request::Request::Heartbeat(engine_proto::Heartbeat {});
+        requests.send(SessionRequest { request: Some(heartbeat) }).await.unwrap();
+        let response = responses.message().await.unwrap().unwrap().response;
+        assert!(matches!(response, Some(session_response::Response::Heartbeat(_))));
+    }
+
+    /// Submit a resting cancel-on-disconnect bid, returning its id
+    async fn submit_flagged(requests: &tokio::sync::mpsc::Sender<SessionRequest>, responses: &mut tonic::Streaming<SessionResponse>) -> Uuid {
+        let order = engine_proto::Order {
+            market: "BTC-USD".to_string(),
+            side_enum: engine_proto::Side::Buy as i32,
+            price: 50000,
+            quantity: 10,
+            cancel_on_disconnect: true,
+            ..Default::default()
+        };
+        let submit = session_request::Request::Submit(order);
+        requests.send(SessionRequest { request: Some(submit) }).await.unwrap();
+        match responses.message().await.unwrap().unwrap().response {
+            Some(session_response::Response::Submitted(submitted)) => {
+                assert_eq!(submitted.rested_quantity, 10);
+                Uuid::parse_str(&submitted.order_id).unwrap()
+            }
+            other => panic!("expected a submitted order, got {:?}", other),
+        }
+    }
+
+    async fn is_live(engine: &GrpcEngine, order_id: Uuid) -> bool {
+        let registry = engine.registry.lock().await;
+        registry.get_market("BTC-USD").unwrap().owner(order_id).is_some()
+    }
+
+    #[tokio::test]
+    async fn test_order_session_requires_logon() {
+        let mut client = serve(session_engine()).await;
+        let (_requests, mut responses) = open_session(&mut client, session_request::Request::Heartbeat(engine_proto::Heartbeat {})).await;
+
+        let status = responses.message().await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn test_order_session_cancels_after_grace_period() {
+        let engine = session_engine();
+        let mut client = serve(engine.clone()).await;
+        let (requests, mut responses) = open_session(&mut client, logon("test-wallet")).await;
+        heartbeat(&requests, &mut responses).await;
+        let order_id = submit_flagged(&requests, &mut responses).await;
+
+        // Closing the stream starts the grace period rather than cancelling
+        drop(requests);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(is_live(&engine, order_id).await);
+
+        // The session only finishes once the grace period is over
+        assert!(responses.message().await.unwrap().is_none());
+        assert!(!is_live(&engine, order_id).await);
+        let report = engine.registry.lock().await.order("BTC-USD", order_id).unwrap().unwrap();
+        assert_eq!(report.record.status, OrderStatus::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn test_order_session_reconnect_keeps_orders_until_heartbeat_timeout() {
+        let engine = session_engine();
+        let mut client = serve(engine.clone()).await;
+        let (requests, mut responses) = open_session(&mut client, logon("test-wallet")).await;
+        let order_id = submit_flagged(&requests, &mut responses).await;
+        drop(requests);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        // Reconnecting within the grace period adopts the orders
+        let (requests, mut responses) = open_session(&mut client, logon("test-wallet")).await;
+        heartbeat(&requests, &mut responses).await;
+        tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        assert!(is_live(&engine, order_id).await);
+
+        // Going quiet times the session out, and the grace period after it
+        // pulls the orders
+        let status = responses.message().await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+        assert!(is_live(&engine, order_id).await);
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        assert!(!is_live(&engine, order_id).await);
+        drop(requests);
+    }
+
+    #[test]
+    fn test_mmp_counts_batch_fills() {
+        let mut engine = batch_engine(BatchArrival::Randomised);
+        engine.set_mmp("mm", Some(MmpLimits { window_ms: 1000, quantity: 0, delta: 0, fills: 2 }));
+
+        for price in [100, 100, 100, 105] {
+            engine.submit(quote(Side::Sell, price, 5));
+        }
+        engine.submit(create_order(Side::Buy, 100, 10));
+        engine.drain_events();
+
+        // The uncross fills two asks, which trips protection and pulls the rest
+        assert!(engine.tick(100).is_empty());
+        let events = engine.drain_events();
+        let traded: u64 = events.iter()
+            .filter_map(|e| match e {
+                EngineEvent::TradeExecuted { trade, .. } => Some(trade.quantity),
+                _ => None,
+            })
+            .sum();
+        assert_eq!(traded, 10);
+        assert!(engine.mmp("mm").unwrap().triggered);
+        assert!(engine.orderbook.wallet_orders("mm").next().is_none());
+        assert!(events.iter().any(|e| matches!(
+            e,
+            EngineEvent::MmpTriggered { cancelled_orders, .. } if cancelled_orders.len() == 2
+        )));
+    }
+
+    #[test]
+    fn test_amend_untriggered_stop() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        let first = create_market_order(Side::Buy, 10, OrderType::Stop { trigger: Price(51000) });
+        let second = create_market_order(Side::Buy, 10, OrderType::Stop { trigger: Price(51000) });
+        engine.submit(first.clone());
+        engine.submit(second.clone());
+        let queue = |engine: &MatchingEngine| -> Vec<(Uuid, u64)> {
+            engine.stops.buys[&Price(51000)].iter().map(|o| (o.id, o.quantity)).collect()
+        };
+
+        let amended = engine.amend(amendment(&first, None, Some(5)));
+        assert!(amended.priority_kept);
+        assert_eq!(amended.outcome.parked, 5);
+        assert_eq!(queue(&engine), vec![(first.id, 5), (second.id, 10)]);
+
+        let amended = engine.amend(amendment(&first, None, Some(15)));
+        assert!(!amended.priority_kept);
+        assert!(amended.outcome.rejected.is_none());
+        assert_eq!(queue(&engine), vec![(second.id, 10), (first.id, 15)]);
+        assert_eq!(engine.report(first.id).unwrap().record.quantity, 15);
+    }
+
+    #[test]
+    fn test_amending_a_stop_buy_cap_reprices_its_escrow() {
+        let mut registry = ledger_registry(FeeSchedule::default());
+        let stop = Order {
+            wallet: "buyer".to_string(),
+            price: Price(300),
+            ..create_market_order(Side::Buy, 10, OrderType::Stop { trigger: Price(300) })
+        };
+        registry.submit(stop.clone()).unwrap();
+        assert_eq!(balance(&registry, "buyer", "USD"), (7_000, 3_000));
+
+        let amended = registry.amend(amendment(&stop, Some(200), None)).unwrap();
+        assert_eq!(amended.outcome.parked, 10);
+        assert_eq!(balance(&registry, "buyer", "USD"), (8_000, 2_000));
+    }
+
+    #[test]
+    fn test_registry_validates_orders_amends_and_replacements() {
+        let mut registry = btc_registry();
+        let iceberg_market = Order {
+            display_quantity: Some(5),
+            ..create_market_order(Side::Buy, 10, OrderType::Market)
+        };
+        assert_eq!(registry.submit(iceberg_market).unwrap_err(), EngineError::IcebergMarketOrder);
+
+        let order = create_order(Side::Buy, 50000, 10);
+        registry.submit(order.clone()).unwrap();
+        assert_eq!(registry.amend(amendment(&order, None, Some(0))).unwrap_err(), EngineError::InvalidQuantity);
+        let replacement = Order { quantity: 0, ..order.clone() };
+        assert_eq!(registry.replace(replacement).unwrap_err(), EngineError::InvalidQuantity);
+        assert!(registry.get_market("BTC-USD").unwrap().orderbook.contains(order.id));
+    }
+
+    #[test]
+    fn test_market_buy_stops_at_the_depth_it_reserved_for() {
+        let mut registry = ledger_registry(FeeSchedule::default());
+        registry.deposit("mm", "BTC", 10, 0).unwrap();
+        registry.set_mmp("BTC-USD", "mm", Some(MmpLimits { window_ms: 1000, quantity: 0, delta: 0, fills: 1 })).unwrap();
+        registry.submit(quote(Side::Sell, 100, 5)).unwrap();
+        registry.submit(quote(Side::Sell, 101, 5)).unwrap();
+        registry.submit(Order { wallet: "seller".to_string(), ..create_order(Side::Sell, 1000, 5) }).unwrap();
+
+        // The first fill pulls the 101 ask, but the buy does not go on to 1000
+        let buy = Order { wallet: "buyer".to_string(), ..create_market_order(Side::Buy, 10, OrderType::Market) };
+        let outcome = registry.submit(buy).unwrap();
+        assert_eq!((outcome.filled, outcome.cancelled), (5, 5));
+        assert_eq!(balance(&registry, "buyer", "USD"), (10_000 - 500, 0));
+        assert_eq!(balance(&registry, "buyer", "BTC"), (5, 0));
+        assert_eq!(balance(&registry, "seller", "USD"), (0, 0));
+    }
+
+    #[test]
+    fn test_replacing_a_gtd_order_drops_its_expiry() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        let gtd = create_order_tif(Side::Buy, 50000, 5, TimeInForce::Gtd { expires_at: 1_000 });
+        engine.submit(gtd.clone());
+
+        let gtc = Order { time_in_force: TimeInForce::Gtc, ..gtd.clone() };
+        engine.replace(gtc).unwrap();
+        assert!(engine.tick(2_000).is_empty());
+        assert_eq!(engine.report(gtd.id).unwrap().record.status, OrderStatus::New);
+        assert!(engine.orderbook.contains(gtd.id));
+    }
+
+    #[test]
+    fn test_batch_uncross_applies_self_trade_prevention() {
+        let mut engine = batch_engine(BatchArrival::Randomised);
+        let stp = |order: Order| Order { self_trade_prevention: SelfTradePrevention::CancelNewest, ..order };
+        let sell = stp(create_order(Side::Sell, 100, 10));
+        let buy = stp(Order { timestamp: 50, ..create_order(Side::Buy, 100, 10) });
+        engine.submit(sell.clone());
+        engine.submit(buy.clone());
+        engine.drain_events();
+
+        engine.tick(100);
+        let events = engine.drain_events();
+        assert!(!events.iter().any(|e| matches!(e, EngineEvent::TradeExecuted { .. })));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            EngineEvent::SelfTradePrevented { taker_order_id, taker_cancelled: 10, maker_cancelled: 0, .. }
+                if *taker_order_id == buy.id
+        )));
+        assert_eq!(engine.report(buy.id).unwrap().record.status, OrderStatus::Cancelled);
+        assert!(engine.orderbook.contains(sell.id));
+    }
+
+    #[test]
+    fn test_amend_refused_in_call_period_keeps_order() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        let order = create_order_tif(Side::Buy, 50000, 5, TimeInForce::PostOnly);
+        engine.submit(order.clone());
+        engine.set_state(TradingState::PreOpen).unwrap();
+
+        let amended = engine.amend(amendment(&order, Some(49000), None));
+        assert_eq!(amended.outcome.rejected, Some(RejectReason::NotAllowedInAuction));
+        assert_eq!(engine.orderbook.get(order.id).map(|o| o.price), Some(Price(50000)));
+        assert_eq!(engine.report(order.id).unwrap().record.status, OrderStatus::New);
+    }
+
+    /// Session engine that accepts "ops" as an operator identity
+    fn operator_engine() -> GrpcEngine {
+        GrpcEngine {
+            operators: std::sync::Arc::new(["ops".to_string()].into_iter().collect()),
+            ..session_engine()
+        }
+    }
+
+    /// Wrap `message` in a request the transport authenticated as `identity`
+    fn authenticated<T>(message: T, identity: &str) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request.metadata_mut().insert("x-wallet", identity.parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn test_deposit_requires_an_operator() {
+        let engine = operator_engine();
+        let mut client = serve(engine.clone()).await;
+        let deposit = || engine_proto::DepositRequest {
+            wallet: "custody-wallet".to_string(),
+            asset: "USD".to_string(),
+            amount: "1000".to_string(),
+        };
+
+        let status = client.deposit(deposit()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        let status = client.deposit(authenticated(deposit(), "test-wallet")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert_eq!(balance(&*engine.registry.lock().await, "custody-wallet", "USD"), (0, 0));
+
+        client.deposit(authenticated(deposit(), "ops")).await.unwrap();
+        assert_eq!(balance(&*engine.registry.lock().await, "custody-wallet", "USD"), (1000, 0));
+    }
+
+    #[tokio::test]
+    async fn test_kill_switch_requires_an_operator() {
+        let engine = operator_engine();
+        let order_id = engine.registry.lock().await.submit(create_order(Side::Buy, 50000, 10)).unwrap().order_id;
+        let mut client = serve(engine.clone()).await;
+        let engage = || engine_proto::SetKillSwitchRequest { wallet: "test-wallet".to_string(), engaged: true };
+
+        let status = client.set_kill_switch(engage()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        let status = client.set_kill_switch(authenticated(engage(), "test-wallet")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(is_live(&engine, order_id).await);
+
+        let response = client.set_kill_switch(authenticated(engage(), "ops")).await.unwrap().into_inner();
+        assert_eq!(response.cancelled_orders, vec![order_id.to_string()]);
+        assert!(!is_live(&engine, order_id).await);
+    }
+
+    #[test]
+    fn test_indicative_price_leaves_out_iceberg_reserves() {
+        let mut engine = MatchingEngine::new("BTC-USD");
+        engine.set_state(TradingState::PreOpen).unwrap();
+        engine.submit(create_iceberg(Side::Sell, 100, 30, 5));
+        engine.drain_events();
+
+        engine.submit(create_order(Side::Buy, 100, 20));
+        assert!(matches!(
+            engine.drain_events().last(),
+            Some(EngineEvent::IndicativePrice { price: Some(Price(100)), volume: 5, imbalance: 15, .. })
+        ));
+
+        // The uncross itself still trades against the reserve
+        let trades = engine.set_state(TradingState::Continuous).unwrap();
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<u64>(), 20);
+    }
+
+    #[test]
+    fn test_price_collar_measures_market_orders_at_reachable_price() {
+        let mut registry = risk_registry(RiskLimits {
+            price_collar: Some(PriceCollar { reference: CollarReference::LastTrade, bps: 500 }),
+            ..RiskLimits::default()
+        });
+        // The book fills in before there is a reference to collar it
+        registry.submit(quote(Side::Sell, 100, 10)).unwrap();
+        registry.submit(quote(Side::Sell, 120, 5)).unwrap();
+        registry.submit(quote(Side::Buy, 94, 5)).unwrap();
+        registry.submit(create_order(Side::Buy, 100, 5)).unwrap();
+
+        let sweep = create_market_order(Side::Buy, 10, OrderType::Market);
+        assert_eq!(registry.submit(sweep).unwrap_err(), EngineError::PriceOutsideCollar);
+        let sweep = create_market_order(Side::Sell, 5, OrderType::Market);
+        assert_eq!(registry.submit(sweep).unwrap_err(), EngineError::PriceOutsideCollar);
+
+        // A protection bound inside the collar keeps the order within it
+        let protected = create_market_order(Side::Buy, 10, OrderType::ProtectedMarket(Protection::WorstPrice(Price(104))));
+        assert_eq!(registry.submit(protected).unwrap().trades.len(), 1);
+        let sweep = create_market_order(Side::Buy, 5, OrderType::Market);
+        assert_eq!(registry.submit(sweep).unwrap_err(), EngineError::PriceOutsideCollar);
+    }
 }